@@ -0,0 +1,198 @@
+use anyhow::{anyhow, bail};
+
+use std::env;
+use std::path::Path;
+
+use crate::core::Target;
+use crate::x11_utils::XContext;
+
+#[cfg(feature = "bspwm")]
+use crate::bspwm;
+#[cfg(feature = "ewmh")]
+use crate::ewmh;
+#[cfg(feature = "herbstluftwm")]
+use crate::herbstluftwm;
+#[cfg(feature = "i3")]
+use crate::i3;
+#[cfg(feature = "leftwm")]
+use crate::leftwm;
+#[cfg(feature = "sway")]
+use crate::sway;
+
+// Backends, that were compiled in. Each of them can be left out by disabling
+// the cargo feature with the same name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    #[cfg(feature = "i3")]
+    I3,
+    #[cfg(feature = "bspwm")]
+    Bspwm,
+    #[cfg(feature = "herbstluftwm")]
+    Herbstluftwm,
+    #[cfg(feature = "leftwm")]
+    Leftwm,
+    #[cfg(feature = "sway")]
+    Sway,
+    #[cfg(feature = "ewmh")]
+    Ewmh,
+}
+
+impl Backend {
+    pub const ALL: &'static [Backend] = &[
+        #[cfg(feature = "i3")]
+        Backend::I3,
+        #[cfg(feature = "bspwm")]
+        Backend::Bspwm,
+        #[cfg(feature = "herbstluftwm")]
+        Backend::Herbstluftwm,
+        #[cfg(feature = "leftwm")]
+        Backend::Leftwm,
+        #[cfg(feature = "sway")]
+        Backend::Sway,
+        #[cfg(feature = "ewmh")]
+        Backend::Ewmh,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "i3")]
+            Backend::I3 => "i3",
+            #[cfg(feature = "bspwm")]
+            Backend::Bspwm => "bspwm",
+            #[cfg(feature = "herbstluftwm")]
+            Backend::Herbstluftwm => "herbstluftwm",
+            #[cfg(feature = "leftwm")]
+            Backend::Leftwm => "leftwm",
+            #[cfg(feature = "sway")]
+            Backend::Sway => "sway",
+            #[cfg(feature = "ewmh")]
+            Backend::Ewmh => "ewmh",
+        }
+    }
+
+    fn find(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|x| x.name() == name)
+    }
+
+    // Used for the `--wm` option and the `wm` key in config file
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Self::find(&name.to_lowercase()).ok_or_else(|| {
+            anyhow!("Unknown backend `{name}`. {}", available_backends())
+        })
+    }
+
+    // Returns backend for the window manager with the given _NET_WM_NAME.
+    // Forks of i3 report names like `i3-gaps`, so only prefix is checked
+    fn from_wm_name(wm_name: &str) -> Option<Self> {
+        let wm_name = wm_name.to_lowercase();
+
+        Self::ALL.iter().copied().find(|x| match x.name() {
+            "i3" => wm_name.starts_with("i3"),
+            // These two are never reported in _NET_WM_NAME
+            "sway" | "ewmh" => false,
+            name => wm_name == name,
+        })
+    }
+
+    pub fn detect(x_context: Option<&XContext>) -> anyhow::Result<Self> {
+        // Sway doesn't set any X properties, so it's detected by its socket
+        if env::var_os("SWAYSOCK").is_some() {
+            if let Some(backend) = Self::find("sway") {
+                return Ok(backend);
+            }
+        }
+
+        let Some(x_context) = x_context else {
+            bail!("Couldn't connect to the X server. {}", available_backends());
+        };
+
+        let wm_name = x_context.get_current_wm().ok();
+
+        if let Some(backend) = wm_name.as_deref().and_then(Self::from_wm_name) {
+            return Ok(backend);
+        }
+
+        // Some window managers don't set their name or are not supported
+        // natively, but still support EWMH
+        if let Some(backend) = Self::find("ewmh") {
+            return Ok(backend);
+        }
+
+        match wm_name {
+            Some(name) => bail!(
+                "Window manager `{name}` is not supported. {}",
+                available_backends()
+            ),
+            None => bail!(
+                "Couldn't detect window manager. {}",
+                available_backends()
+            ),
+        }
+    }
+
+    pub fn exec(
+        self,
+        #[cfg_attr(not(feature = "x11"), allow(unused_variables))]
+        x_context: Option<XContext>,
+        target: &Target,
+        config_path: Option<&Path>,
+    ) -> anyhow::Result<()> {
+        #[cfg(feature = "x11")]
+        let require_x_context = || {
+            x_context.ok_or_else(|| anyhow!("Couldn't connect to the X server"))
+        };
+
+        match self {
+            #[cfg(feature = "i3")]
+            Backend::I3 => i3::exec(require_x_context()?, target, config_path),
+            #[cfg(feature = "bspwm")]
+            Backend::Bspwm => {
+                bspwm::exec(require_x_context()?, target, config_path)
+            }
+            #[cfg(feature = "herbstluftwm")]
+            Backend::Herbstluftwm => {
+                herbstluftwm::exec(require_x_context()?, target, config_path)
+            }
+            #[cfg(feature = "leftwm")]
+            Backend::Leftwm => {
+                leftwm::exec(require_x_context()?, target, config_path)
+            }
+            #[cfg(feature = "sway")]
+            Backend::Sway => sway::exec(target, config_path),
+            #[cfg(feature = "ewmh")]
+            Backend::Ewmh => {
+                ewmh::exec(require_x_context()?, target, config_path)
+            }
+        }
+    }
+}
+
+pub fn available_backends() -> String {
+    let names: Vec<_> = Backend::ALL.iter().map(|x| x.name()).collect();
+
+    format!("Available backends: {}", names.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_from_name_works() {
+        for &backend in Backend::ALL {
+            assert_eq!(Backend::from_name(backend.name()).unwrap(), backend);
+        }
+
+        let error = Backend::from_name("dwm").unwrap_err().to_string();
+        assert!(error.contains(&available_backends()));
+    }
+
+    #[test]
+    #[cfg(all(feature = "i3", feature = "leftwm"))]
+    fn backend_from_wm_name_works() {
+        assert_eq!(Backend::from_wm_name("i3"), Some(Backend::I3));
+        assert_eq!(Backend::from_wm_name("i3-gaps"), Some(Backend::I3));
+        assert_eq!(Backend::from_wm_name("LeftWM"), Some(Backend::Leftwm));
+        assert_eq!(Backend::from_wm_name("Openbox"), None);
+    }
+}
@@ -1,7 +1,12 @@
+use anyhow::Context as _;
+
 use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
 
 use crate::config::BspwmConfig;
-use crate::core::{WmCore, WmCoreFeatures as _};
+use crate::core::{CoreEvent, Target, WmCore};
+use crate::x11_utils::XContext;
 use bspc_rs::events::{self, DesktopEvent, Event, NodeEvent, Subscription};
 
 pub struct BspwmConnection;
@@ -12,9 +17,16 @@ impl BspwmConnection {
     }
 }
 
-pub fn exec(monitor_name: Option<&str>, config_file: Option<&Path>) {
-    let mut core = WmCore::init(monitor_name, config_file);
-    core.process_start();
+pub fn exec(
+    x_context: XContext,
+    target: &Target,
+    config_file: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut cores = WmCore::init_all(Some(x_context), target, config_file)?;
+
+    for core in &mut cores {
+        core.process_start();
+    }
 
     let subscriptions = [
         Subscription::NodeFocus,
@@ -22,37 +34,58 @@ pub fn exec(monitor_name: Option<&str>, config_file: Option<&Path>) {
         Subscription::NodeFlag,
         Subscription::NodeState,
         Subscription::DesktopFocus,
+        Subscription::MonitorAdd,
+        Subscription::MonitorGeometry,
     ];
 
     let mut subscriber = events::subscribe(false, None, &subscriptions)
-        .expect("Couldn't subscribe to events");
+        .context("Couldn't subscribe to events")?;
+
+    let (sender, receiver) = mpsc::channel();
+    cores[0].listen_x11_events(sender.clone());
+
+    thread::spawn(move || {
+        for raw_event in subscriber.events() {
+            match raw_event {
+                Ok(event) => {
+                    if sender.send(CoreEvent::Wm(event)).is_err() {
+                        break;
+                    }
+                }
 
-    for raw_event in subscriber.events() {
-        match raw_event {
-            Ok(event) => {
-                core.handle_event(event);
+                Err(e) => {
+                    eprintln!("While listening to events, encounter the following error: {e}");
+                }
             }
+        }
+    });
 
-            Err(e) => {
-                println!("While listening to events, encounter the following error: {e}");
-            }
+    WmCore::run(cores, receiver, target, config_file, |core, event| {
+        if let CoreEvent::Wm(event) = event {
+            core.handle_event(event);
         }
-    }
+    });
+
+    Ok(())
 }
 
 impl WmCore<BspwmConnection, BspwmConfig> {
-    fn handle_event(&mut self, event: Event) {
+    fn handle_event(&mut self, event: &Event) {
         match event {
             Event::NodeEvent(e) => self.handle_node_event(e),
             Event::DesktopEvent(e) => self.handle_desktop_event(e),
+            Event::MonitorEvent(_) => self.process_monitor_change(),
             _ => unreachable!(),
         }
     }
 
-    fn handle_node_event(&mut self, event: NodeEvent) {
+    fn handle_node_event(&mut self, event: &NodeEvent) {
         match event {
+            // Node can be focused on the other monitor
             NodeEvent::NodeFocus(node_info) => {
-                self.process_focused_window(node_info.node_id);
+                if self.get_focused_window_id() == Some(node_info.node_id) {
+                    self.process_focused_window(node_info.node_id);
+                }
             }
 
             NodeEvent::NodeRemove(_) => {
@@ -69,14 +102,20 @@ impl WmCore<BspwmConnection, BspwmConfig> {
                 // NodeFlag event can in particular mean, that node can become
                 // hidden and we need to check if that was the only visible
                 // node on that desktop
-                if self.is_desk_empty(node_info.desktop_id) {
+                let desktop_id = node_info.desktop_id;
+
+                if self.get_focused_desktop_id() == Some(desktop_id)
+                    && self.is_desk_empty(desktop_id)
+                {
                     self.process_empty_desktop();
                 }
             }
 
             NodeEvent::NodeState(node_info) => {
                 // println!("{:#?}", node_info);
-                self.process_focused_window(node_info.node_id);
+                if self.get_focused_window_id() == Some(node_info.node_id) {
+                    self.process_focused_window(node_info.node_id);
+                }
             }
             _ => {
                 unreachable!();
@@ -84,11 +123,16 @@ impl WmCore<BspwmConnection, BspwmConfig> {
         }
     }
 
-    fn handle_desktop_event(&mut self, event: DesktopEvent) {
+    fn handle_desktop_event(&mut self, event: &DesktopEvent) {
         match event {
             DesktopEvent::DesktopFocus(event_info) => {
                 let current_desktop = event_info.desktop_id;
 
+                // Desktop can be focused on the other monitor
+                if self.get_focused_desktop_id() != Some(current_desktop) {
+                    return;
+                }
+
                 if self.is_desk_empty(current_desktop) {
                     self.process_empty_desktop();
                 }
@@ -1,12 +1,13 @@
 #![allow(clippy::enum_variant_names)]
 
+use anyhow::{bail, Context as _};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 use std::cmp::min;
 use std::collections::HashMap;
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::fs;
 use std::path::{Path, PathBuf};
 
 mod utils {
@@ -30,11 +31,203 @@ pub struct CommonConfig {
     cache_dir: PathBuf,
     color: String,
 
+    #[serde(default)]
+    icon_mode: IconMode,
+
+    #[serde(default = "default_alpha_threshold")]
+    alpha_threshold: u8,
+
+    #[serde(default)]
+    scale: Scale,
+
+    #[serde(default)]
+    placement: Placement,
+
+    #[serde(default = "default_bars")]
+    bars: Vec<BarMatcher>,
+
+    // Put icon window into the bar instead of placing it over the bar
+    #[serde(default)]
+    reparent: bool,
+
+    #[serde(default)]
+    actions: Actions,
+
     #[serde(rename = "print_info")]
     #[serde(default)]
     print_info_settings: PrintInfoSettings,
 }
 
+// How icon transparency is handled, when there is no compositor running
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum IconMode {
+    // Flatten icon onto the background `color`
+    #[default]
+    Fill,
+
+    // Cut transparent pixels out of the icon window with the X SHAPE
+    // extension
+    Shape,
+
+    // Blend icon onto the pixels of the bar under it, right before drawing
+    Sample,
+}
+
+fn default_alpha_threshold() -> u8 {
+    128
+}
+
+// What is done with the window, when its icon is clicked or scrolled
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    #[default]
+    None,
+
+    Focus,
+
+    // Asks window to close itself with WM_DELETE_WINDOW
+    Close,
+
+    Minimize,
+    ToggleFullscreen,
+
+    // Focus next or previous window on the current desktop
+    FocusNext,
+    FocusPrevious,
+
+    // Shell command, which gets id, class of the window and name of the
+    // monitor in IXWINDOW_WINDOW_ID, IXWINDOW_WINDOW_CLASS and
+    // IXWINDOW_MONITOR variables
+    Command(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct Actions {
+    pub left: Action,
+    pub middle: Action,
+    pub right: Action,
+    pub scroll_up: Action,
+    pub scroll_down: Action,
+}
+
+impl Default for Actions {
+    fn default() -> Self {
+        Self {
+            left: Action::None,
+            middle: Action::None,
+            right: Action::None,
+            scroll_up: Action::FocusPrevious,
+            scroll_down: Action::FocusNext,
+        }
+    }
+}
+
+impl Actions {
+    // Buttons are numbered the way X server does it
+    #[cfg(feature = "x11")]
+    pub fn get(&self, button: u8) -> &Action {
+        match button {
+            1 => &self.left,
+            2 => &self.middle,
+            3 => &self.right,
+            4 => &self.scroll_up,
+            5 => &self.scroll_down,
+            _ => &Action::None,
+        }
+    }
+}
+
+// Rule for finding windows of the bar. Window matches it, if all the given
+// fields match, so the rule without any of them matches nothing. Rule with
+// `monitor` is used only for the bar on that monitor
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct BarMatcher {
+    pub monitor: Option<String>,
+    pub instance: Option<String>,
+    pub class: Option<String>,
+
+    // Window has _NET_WM_WINDOW_TYPE_DOCK type
+    pub dock: bool,
+
+    // Window reserves space at the edge of the screen with _NET_WM_STRUT or
+    // _NET_WM_STRUT_PARTIAL
+    pub strut: bool,
+}
+
+impl BarMatcher {
+    pub fn is_empty(&self) -> bool {
+        self.instance.is_none()
+            && self.class.is_none()
+            && !self.dock
+            && !self.strut
+    }
+
+    pub fn applies_to(&self, monitor_name: &str) -> bool {
+        self.monitor.as_ref().is_none_or(|x| x == monitor_name)
+    }
+}
+
+fn default_bars() -> Vec<BarMatcher> {
+    vec![BarMatcher {
+        instance: Some("polybar".to_string()),
+        class: Some("Polybar".to_string()),
+        ..Default::default()
+    }]
+}
+
+// How `x`, `y` and `size` of the icon are interpreted
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Placement {
+    // Icon of `size` is placed at `x` and `y` on the monitor
+    #[default]
+    Absolute,
+
+    // Icon fills the height of the bar on the monitor except for `y` pixels
+    // above and below it, and `x` is the offset from the left edge of the bar
+    Bar,
+}
+
+// Factor, which `size`, `x` and `y` are multiplied by, so that icon has the
+// same look on monitors with different DPI
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Scale {
+    // The same factor for all monitors
+    Fixed(f32),
+
+    // Factor, that is detected automatically
+    Auto(ScaleSource),
+
+    // Factor of each monitor by its name. Monitors, that are not listed,
+    // are not scaled
+    PerMonitor(HashMap<String, f32>),
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self::Fixed(1.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleSource {
+    // `Xft.dpi` from the X resources, which is the same for all monitors
+    Xft,
+
+    // Physical size of the monitor, reported by RandR
+    Randr,
+}
+
 #[derive(
     Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Hash, Eq,
 )]
@@ -141,6 +334,7 @@ impl PrintInfoSettings {
     }
 }
 
+#[cfg(feature = "i3")]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct I3Config {
     #[serde(flatten)]
@@ -149,12 +343,41 @@ pub struct I3Config {
     pub gap_per_desk: f32,
 }
 
+#[cfg(feature = "bspwm")]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BspwmConfig {
     #[serde(flatten)]
     common_config: CommonConfig,
 }
 
+#[cfg(feature = "ewmh")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EwmhConfig {
+    #[serde(flatten)]
+    common_config: CommonConfig,
+}
+
+#[cfg(feature = "herbstluftwm")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HerbstluftwmConfig {
+    #[serde(flatten)]
+    common_config: CommonConfig,
+}
+
+#[cfg(feature = "sway")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SwayConfig {
+    #[serde(flatten)]
+    common_config: CommonConfig,
+}
+
+#[cfg(feature = "leftwm")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LeftwmConfig {
+    #[serde(flatten)]
+    common_config: CommonConfig,
+}
+
 pub trait Config {
     fn common_config(&self) -> &CommonConfig;
 
@@ -188,64 +411,182 @@ pub trait Config {
         self.common_config().size
     }
 
+    fn icon_mode(&self) -> IconMode {
+        self.common_config().icon_mode
+    }
+
+    fn alpha_threshold(&self) -> u8 {
+        self.common_config().alpha_threshold
+    }
+
+    fn scale(&self) -> &Scale {
+        &self.common_config().scale
+    }
+
+    fn placement(&self) -> Placement {
+        self.common_config().placement
+    }
+
+    fn bars(&self) -> &[BarMatcher] {
+        &self.common_config().bars
+    }
+
+    fn reparent(&self) -> bool {
+        self.common_config().reparent
+    }
+
+    #[cfg(feature = "x11")]
+    fn actions(&self) -> &Actions {
+        &self.common_config().actions
+    }
+
     fn print_info_settings(&self) -> &PrintInfoSettings {
         &self.common_config().print_info_settings
     }
 }
 
+#[cfg(feature = "i3")]
 impl Config for I3Config {
     fn common_config(&self) -> &CommonConfig {
         &self.common_config
     }
 }
 
+#[cfg(feature = "bspwm")]
 impl Config for BspwmConfig {
     fn common_config(&self) -> &CommonConfig {
         &self.common_config
     }
 }
 
-pub fn read_to_table(config_file: Option<&Path>) -> toml::Table {
-    let config_filename = if let Some(name) = config_file {
-        name.to_path_buf()
-    } else {
-        locate_config_file().expect("Couldn't find config file")
+#[cfg(feature = "ewmh")]
+impl Config for EwmhConfig {
+    fn common_config(&self) -> &CommonConfig {
+        &self.common_config
+    }
+}
+
+#[cfg(feature = "herbstluftwm")]
+impl Config for HerbstluftwmConfig {
+    fn common_config(&self) -> &CommonConfig {
+        &self.common_config
+    }
+}
+
+#[cfg(feature = "sway")]
+impl Config for SwayConfig {
+    fn common_config(&self) -> &CommonConfig {
+        &self.common_config
+    }
+}
+
+#[cfg(feature = "leftwm")]
+impl Config for LeftwmConfig {
+    fn common_config(&self) -> &CommonConfig {
+        &self.common_config
+    }
+}
+
+pub fn read_to_table(
+    config_file: Option<&Path>,
+) -> anyhow::Result<toml::Table> {
+    let config_filename = match config_file {
+        Some(name) => name.to_path_buf(),
+        None => locate_config_file().context("Couldn't find config file")?,
     };
 
     let config_filename = expand_filename(&config_filename);
+    let config_str =
+        fs::read_to_string(&config_filename).with_context(|| {
+            format!(
+                "Failed to open config file `{}`",
+                config_filename.display()
+            )
+        })?;
 
-    let mut config_file =
-        File::open(config_filename).expect("Failed to open config file");
-    let mut config_str = String::new();
-    config_file.read_to_string(&mut config_str).unwrap();
-
-    config_str.parse().unwrap()
+    config_str.parse().context("Couldn't parse config file")
 }
 
-pub fn load_i3(config_file: Option<&Path>) -> I3Config {
-    let mut table = read_to_table(config_file);
+// Reads the section of the backend, like `[i3]`, from the config file
+fn load_section<T: DeserializeOwned>(
+    config_file: Option<&Path>,
+    section: &str,
+) -> anyhow::Result<T> {
+    let mut table = read_to_table(config_file)?;
 
     // We use remove here, because we need ownership for try_into
-    let config_table = table.remove("i3").unwrap();
+    let Some(config_table) = table.remove(section) else {
+        bail!("Section `[{section}]` is missing in the config file");
+    };
+
+    config_table
+        .try_into()
+        .with_context(|| format!("Couldn't parse section `[{section}]`"))
+}
 
-    let mut i3_config: I3Config = config_table.try_into().unwrap();
+#[cfg(feature = "i3")]
+pub fn load_i3(config_file: Option<&Path>) -> anyhow::Result<I3Config> {
+    let mut i3_config: I3Config = load_section(config_file, "i3")?;
     i3_config.common_config.cache_dir =
         expand_filename(&i3_config.common_config.cache_dir);
 
-    i3_config
+    Ok(i3_config)
 }
 
-pub fn load_bspwm(config_file: Option<&Path>) -> BspwmConfig {
-    let mut table = read_to_table(config_file);
-
-    // We use remove here, because we need ownership for try_into
-    let config_table = table.remove("bspwm").unwrap();
-
-    let mut bspwm_config: BspwmConfig = config_table.try_into().unwrap();
+#[cfg(feature = "bspwm")]
+pub fn load_bspwm(config_file: Option<&Path>) -> anyhow::Result<BspwmConfig> {
+    let mut bspwm_config: BspwmConfig = load_section(config_file, "bspwm")?;
     bspwm_config.common_config.cache_dir =
         expand_filename(&bspwm_config.common_config.cache_dir);
 
-    bspwm_config
+    Ok(bspwm_config)
+}
+
+#[cfg(feature = "ewmh")]
+pub fn load_ewmh(config_file: Option<&Path>) -> anyhow::Result<EwmhConfig> {
+    let mut ewmh_config: EwmhConfig = load_section(config_file, "ewmh")?;
+    ewmh_config.common_config.cache_dir =
+        expand_filename(&ewmh_config.common_config.cache_dir);
+
+    Ok(ewmh_config)
+}
+
+#[cfg(feature = "herbstluftwm")]
+pub fn load_herbstluftwm(
+    config_file: Option<&Path>,
+) -> anyhow::Result<HerbstluftwmConfig> {
+    let mut herbstluftwm_config: HerbstluftwmConfig =
+        load_section(config_file, "herbstluftwm")?;
+    herbstluftwm_config.common_config.cache_dir =
+        expand_filename(&herbstluftwm_config.common_config.cache_dir);
+
+    Ok(herbstluftwm_config)
+}
+
+#[cfg(feature = "sway")]
+pub fn load_sway(config_file: Option<&Path>) -> anyhow::Result<SwayConfig> {
+    let mut sway_config: SwayConfig = load_section(config_file, "sway")?;
+    sway_config.common_config.cache_dir =
+        expand_filename(&sway_config.common_config.cache_dir);
+
+    Ok(sway_config)
+}
+
+#[cfg(feature = "leftwm")]
+pub fn load_leftwm(config_file: Option<&Path>) -> anyhow::Result<LeftwmConfig> {
+    let mut leftwm_config: LeftwmConfig = load_section(config_file, "leftwm")?;
+    leftwm_config.common_config.cache_dir =
+        expand_filename(&leftwm_config.common_config.cache_dir);
+
+    Ok(leftwm_config)
+}
+
+// Returns name of the backend from the top-level `wm` key, if it's set
+pub fn load_wm(config_file: Option<&Path>) -> anyhow::Result<Option<String>> {
+    let table = read_to_table(config_file)?;
+    let wm = table.get("wm").and_then(|x| x.as_str());
+
+    Ok(wm.map(|x| x.to_string()))
 }
 
 fn locate_config_file() -> Option<PathBuf> {
@@ -294,6 +635,7 @@ fn expand_filename(file: &Path) -> PathBuf {
 mod tests {
     use super::*;
 
+    #[cfg(any(feature = "i3", feature = "ewmh"))]
     const CONFIG_PATH: &str = "./examples/ixwindow.toml";
 
     #[test]
@@ -302,11 +644,15 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "i3")]
     fn parse_config_works() {
         let config_path = PathBuf::from(CONFIG_PATH);
-        let config = load_i3(Some(&config_path));
+        let config = load_i3(Some(&config_path)).unwrap();
 
         assert_eq!(config.size(), 24);
+        assert_eq!(config.icon_mode(), IconMode::Fill);
+        assert_eq!(config.alpha_threshold(), 128);
+        assert_eq!(config.bars(), default_bars());
         assert_eq!(
             config.cache_dir(),
             PathBuf::from(
@@ -319,9 +665,61 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "ewmh")]
+    fn parse_ewmh_config_works() {
+        let config_path = PathBuf::from(CONFIG_PATH);
+        let config = load_ewmh(Some(&config_path)).unwrap();
+
+        assert_eq!(config.size(), 24);
+        assert_eq!(config.x(), 270);
+        assert_eq!(config.icon_mode(), IconMode::Fill);
+    }
+
+    #[test]
+    fn parse_scale_works() {
+        #[derive(Deserialize)]
+        struct Table {
+            scale: Scale,
+        }
+
+        let parse = |x: &str| toml::from_str::<Table>(x).unwrap().scale;
+
+        assert_eq!(parse("scale = 2"), Scale::Fixed(2.0));
+        assert_eq!(parse("scale = 1.5"), Scale::Fixed(1.5));
+        assert_eq!(parse(r#"scale = "xft""#), Scale::Auto(ScaleSource::Xft));
+        assert_eq!(
+            parse(r#"scale = { "eDP-1" = 2 }"#),
+            Scale::PerMonitor(HashMap::from([("eDP-1".to_string(), 2.0)]))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "x11")]
+    fn parse_actions_works() {
+        let actions: Actions = toml::from_str(
+            r#"
+            left = "focus"
+            right = { command = "xkill -id $IXWINDOW_WINDOW_ID" }
+            scroll_up = "none"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(actions.get(1), &Action::Focus);
+        assert_eq!(actions.get(2), &Action::None);
+        assert_eq!(
+            actions.get(3),
+            &Action::Command("xkill -id $IXWINDOW_WINDOW_ID".to_string())
+        );
+        assert_eq!(actions.get(4), &Action::None);
+        assert_eq!(actions.get(5), &Action::FocusNext);
+    }
+
+    #[test]
+    #[cfg(feature = "i3")]
     fn expand_filename_works() {
         let config_path = PathBuf::from(CONFIG_PATH);
-        let config = load_i3(Some(&config_path));
+        let config = load_i3(Some(&config_path)).unwrap();
 
         assert_eq!(
             expand_filename(config.cache_dir()),
@@ -1,21 +1,53 @@
+use anyhow::{bail, Context as _};
+
+#[cfg(feature = "i3")]
 use i3ipc::I3Connection;
-use std::sync::mpsc::{self, Receiver, Sender};
+#[cfg(feature = "x11")]
+use std::sync::mpsc::{Receiver, Sender};
 
 use std::fs;
 use std::path::{Path, PathBuf};
+#[cfg(feature = "x11")]
+use std::process::Command;
 use std::str;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
+#[cfg(feature = "x11")]
 use x11rb::connection::Connection;
-use x11rb::protocol::xproto::ConnectionExt;
-use x11rb::rust_connection::RustConnection;
-
-use crate::bspwm::BspwmConnection;
-use crate::config::{self, BspwmConfig, Config, I3Config, WindowInfoType};
-use crate::i3_utils;
+#[cfg(feature = "x11")]
+use x11rb::protocol::xproto::{
+    AtomEnum, ConfigureNotifyEvent, CreateNotifyEvent, MapNotifyEvent,
+    UnmapNotifyEvent,
+};
+#[cfg(feature = "x11")]
+use x11rb::protocol::Event;
+
+#[cfg(feature = "x11")]
+use crate::config::Action;
+use crate::config::{
+    self, Config, IconMode, Placement, Scale, ScaleSource, WindowInfoType,
+};
+use crate::daemon::Server;
 use crate::wm_connection::WmConnection;
-use crate::x11_utils;
+use crate::x11_utils::{IconStyle, IconWindow, XContext};
+
+#[cfg(feature = "bspwm")]
+use crate::{bspwm::BspwmConnection, config::BspwmConfig};
+#[cfg(feature = "ewmh")]
+use crate::{config::EwmhConfig, ewmh::EwmhConnection};
+#[cfg(feature = "herbstluftwm")]
+use crate::{config::HerbstluftwmConfig, herbstluftwm::HerbstluftwmConnection};
+#[cfg(feature = "i3")]
+use crate::{config::I3Config, i3_utils};
+#[cfg(feature = "leftwm")]
+use crate::{config::LeftwmConfig, leftwm::LeftwmConnection};
+#[cfg(feature = "sway")]
+use crate::{
+    config::SwayConfig,
+    sway::{self, SwayConnection},
+};
 
 #[derive(Debug, Clone)]
 struct Window {
@@ -45,7 +77,6 @@ impl State {
 #[derive(Debug, Clone)]
 struct Icon {
     path: PathBuf,
-    id: u32,
     // app_name: String,
     x: i16,
     y: i16,
@@ -60,29 +91,29 @@ pub struct WindowInfo {
 }
 
 impl WindowInfo {
-    fn print(&self, config: &impl Config) {
-        println!(
+    fn output(&self, config: &impl Config) -> String {
+        format!(
             "{}{}",
             config.gap(),
             config
                 .print_info_settings()
                 .format_info(&self.info, Some(self.info_type))
-        );
+        )
     }
 }
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq)]
 pub struct EmptyInfo {
     pub info: String,
 }
 
 impl EmptyInfo {
-    fn print(&self, config: &impl Config) {
-        println!("{}{}", config.gap(), &self.info);
+    fn output(&self, config: &impl Config) -> String {
+        format!("{}{}", config.gap(), &self.info)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 enum Info {
     WindowInfo(WindowInfo),
     EmptyInfo(EmptyInfo),
@@ -95,24 +126,51 @@ impl Default for Info {
 }
 
 impl Info {
-    fn print(&self, config: &impl Config) {
-        if let Info::EmptyInfo(empty_info) = self {
-            empty_info.print(config);
+    fn output(&self, config: &impl Config) -> String {
+        match self {
+            Info::WindowInfo(window_info) => window_info.output(config),
+            Info::EmptyInfo(empty_info) => empty_info.output(config),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Signal {
-    Stop,
+// Monitors, that are served by the process
+#[derive(Debug, Clone)]
+pub enum Target {
+    // Info of one monitor (primary, if not specified) is printed to stdout
+    Monitor(Option<String>),
+
+    // Info of every monitor is sent to the clients of daemon
+    Daemon(Arc<Server>),
+}
+
+// Events, that are processed in the main loop. They come from the window
+// manager and from the X server (for example, when the title of the focused
+// window changes)
+#[derive(Debug)]
+pub enum CoreEvent<E> {
+    // The ewmh backend gets all the events from the X server, so it never
+    // sends these
+    #[allow(dead_code)]
+    Wm(E),
+    #[cfg(feature = "x11")]
+    X11(Event),
 }
 
 #[derive(Debug, Clone, Default)]
 struct Bar {
     icon: Option<Icon>,
+
+    // Window is created once and reused for all the icons on the monitor
+    icon_window: Option<IconWindow>,
+
+    // Windows of the bars on the monitor, which icon was last stacked above
+    bar_windows: Vec<u32>,
     info: Info,
     state: State,
-    info_controller: Option<Sender<Signal>>,
+
+    // Last line, printed to polybar
+    output: Option<String>,
 }
 
 impl Bar {
@@ -128,18 +186,42 @@ struct Monitor {
     name: String,
     // desktops_number: u32,  // maybe will be useful for i3-desk changing
     bar: Bar,
+
+    // Name is updated, when primary monitor changes, if it wasn't specified
+    #[cfg(feature = "x11")]
+    follows_primary: bool,
+
+    // Geometry of the output or None, if it's disconnected
+    geometry: Option<(i16, i16, u16, u16)>,
+
+    // Factor, which size and offsets of the icon are multiplied by
+    scale: f32,
 }
 
 impl Monitor {
-    fn init(monitor_name: Option<&str>) -> Self {
+    #[cfg(feature = "x11")]
+    fn init(
+        x_context: &XContext,
+        monitor_name: Option<&str>,
+    ) -> anyhow::Result<Self> {
         let name = match monitor_name {
             Some(x) => x.to_string(),
-            None => x11_utils::get_primary_monitor_name()
-                .expect("Couldn't get name of the primary monitor"),
+            None => x_context
+                .get_primary_monitor_name()
+                .context("Couldn't get name of the primary monitor")?,
         };
 
+        Ok(Self {
+            geometry: x_context.get_monitor_geometry(&name).ok(),
+            follows_primary: monitor_name.is_none(),
+            ..Self::with_name(name)
+        })
+    }
+
+    fn with_name(name: String) -> Self {
         Self {
             name,
+            scale: 1.0,
             ..Default::default()
         }
     }
@@ -152,8 +234,14 @@ where
 {
     config: C,
     wm_connection: W,
-    x11rb_connection: RustConnection,
+
+    // There is no X server on Wayland, so icons are not drawn there
+    x_context: Option<Arc<XContext>>,
     monitor: Monitor,
+    icon_style: IconStyle,
+
+    // Info is sent to the clients of daemon instead of stdout, if it's set
+    daemon: Option<Arc<Server>>,
 }
 
 impl<W, C> WmCore<W, C>
@@ -162,80 +250,525 @@ where
     C: Config + Clone + std::marker::Send + 'static,
     WmCore<W, C>: WmCoreFeatures<W, C>,
 {
-    fn watch_and_print_info(&self, signal_recv: Receiver<Signal>) {
-        let window_id = self.monitor.bar.state.curr_window.clone().unwrap().id;
-        let info_types = self.config.print_info_settings().info_types.clone();
-        let config = self.config.clone();
-        let mut window_info = Default::default();
-        let mut prev_window_info = Default::default();
+    // Creates one core for every monitor, that is served by the process.
+    // They share the connection to the X server
+    pub fn init_all(
+        x_context: Option<XContext>,
+        target: &Target,
+        config_file: Option<&Path>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let x_context = x_context.map(Arc::new);
+
+        let daemon = match target {
+            Target::Monitor(monitor_name) => {
+                let mut core = Self::init(
+                    x_context,
+                    monitor_name.as_deref(),
+                    config_file,
+                )?;
+                core.update_scale();
+                return Ok(vec![core]);
+            }
 
-        thread::spawn(move || loop {
-            if signal_recv.try_recv().is_ok() {
-                break;
+            Target::Daemon(daemon) => daemon,
+        };
+
+        let monitor_names = Self::monitor_names(x_context.as_deref());
+
+        if monitor_names.is_empty() {
+            bail!("Couldn't find any monitor");
+        }
+
+        monitor_names
+            .iter()
+            .map(|name| {
+                let mut core =
+                    Self::init(x_context.clone(), Some(name), config_file)?;
+                core.daemon = Some(Arc::clone(daemon));
+                core.update_scale();
+                Ok(core)
+            })
+            .collect()
+    }
+
+    // Daemon serves all the monitors, so cores are created for the monitors,
+    // that are connected later, and dropped for the disconnected ones
+    #[cfg(feature = "x11")]
+    pub fn update_monitors(
+        cores: &mut Vec<Self>,
+        event: &Event,
+        target: &Target,
+        config_file: Option<&Path>,
+    ) {
+        let Target::Daemon(daemon) = target else {
+            return;
+        };
+
+        if !matches!(
+            event,
+            Event::RandrScreenChangeNotify(_) | Event::RandrNotify(_)
+        ) {
+            return;
+        }
+
+        let Some(x_context) = cores.first().and_then(|x| x.x_context.clone())
+        else {
+            return;
+        };
+
+        // Outputs may be disabled all at once for a moment, while they are
+        // reconfigured, so the last core is never dropped
+        let monitor_names = Self::monitor_names(Some(&x_context));
+
+        if monitor_names.is_empty() {
+            return;
+        }
+
+        cores.retain(|x| monitor_names.contains(&x.monitor.name));
+
+        for name in monitor_names {
+            if cores.iter().any(|x| x.monitor.name == name) {
+                continue;
             }
 
-            // TODO: add logging
-            match x11_utils::get_window_info(window_id, &info_types) {
-                Ok(win_info) => {
-                    prev_window_info = window_info;
-                    window_info = win_info;
+            let core = Self::init(
+                Some(Arc::clone(&x_context)),
+                Some(&name),
+                config_file,
+            );
+
+            let mut core = match core {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("Couldn't serve monitor {name}: {e:#}");
+                    continue;
+                }
+            };
+
+            core.daemon = Some(Arc::clone(daemon));
+            core.update_scale();
+            core.process_start();
+            cores.push(core);
+        }
+    }
+
+    // Main loop of the backends, which run on X server. Every event is passed
+    // to all the monitors, each of them checks, if the event concerns it.
+    // `handle_event` is called by the backend before the core handles the
+    // events from the X server by itself
+    #[cfg(feature = "x11")]
+    pub fn run<E>(
+        mut cores: Vec<Self>,
+        receiver: Receiver<CoreEvent<E>>,
+        target: &Target,
+        config_file: Option<&Path>,
+        mut handle_event: impl FnMut(&mut Self, &CoreEvent<E>),
+    ) {
+        for event in receiver {
+            for core in &mut cores {
+                handle_event(core, &event);
+
+                if let CoreEvent::X11(event) = &event {
+                    core.handle_x11_event(event);
                 }
-                Err(_) => {}
             }
 
-            if window_info != prev_window_info {
-                window_info.print(&config);
+            if let CoreEvent::X11(event) = &event {
+                Self::update_monitors(&mut cores, event, target, config_file);
             }
+        }
+    }
+
+    // Forwards all events from the X server to the main loop
+    #[cfg(feature = "x11")]
+    pub fn listen_x11_events<E: Send + 'static>(
+        &self,
+        sender: Sender<CoreEvent<E>>,
+    ) {
+        let Some(x_context) = self.x_context.clone() else {
+            return;
+        };
+
+        // Without RandR events icon just won't follow the changes of outputs
+        if let Err(e) = x_context.select_randr_events() {
+            eprintln!("Couldn't subscribe to RandR events: {e}");
+        }
+
+        if let Err(e) = x_context.select_root_events() {
+            eprintln!("Couldn't subscribe to events of the root window: {e}");
+        }
 
-            thread::sleep(Duration::from_millis(100));
+        thread::spawn(move || loop {
+            match x_context.conn.wait_for_event() {
+                Ok(event) => {
+                    if sender.send(CoreEvent::X11(event)).is_err() {
+                        break;
+                    }
+                }
+
+                Err(e) => {
+                    eprintln!("Lost connection to the X server: {e}");
+                    break;
+                }
+            }
         });
     }
 
-    fn destroy_icon(&mut self) {
-        let conn = &self.x11rb_connection;
-        let bar = &mut self.monitor.bar;
+    #[cfg(feature = "x11")]
+    pub fn handle_x11_event(&mut self, event: &Event) {
+        let Some(x_context) = &self.x_context else {
+            return;
+        };
 
-        if let Some(icon) = &bar.icon {
-            // TODO: add logging
-            conn.destroy_window(icon.id).ok(); // If couldn't destroy, don't do anything
-            conn.flush().unwrap();
+        match event {
+            Event::PropertyNotify(e) => {
+                let Some(window) = &self.monitor.bar.state.curr_window else {
+                    return;
+                };
+
+                let window_id = window.id;
+                let icon_atom = x_context.atoms._NET_WM_ICON;
+                let info_atoms = [
+                    AtomEnum::WM_NAME.into(),
+                    AtomEnum::WM_CLASS.into(),
+                    x_context.atoms._NET_WM_NAME,
+                    x_context.atoms._NET_WM_VISIBLE_NAME,
+                ];
+
+                if e.window == window_id && info_atoms.contains(&e.atom) {
+                    self.update_window_info();
+                }
+
+                if e.window == window_id
+                    && e.atom == icon_atom
+                    && self.is_icon_replaceable()
+                {
+                    self.update_icon(window_id);
+                }
+            }
+
+            Event::RandrScreenChangeNotify(_) | Event::RandrNotify(_) => {
+                self.process_monitor_change();
+            }
+
+            // Icon window is destroyed together with the bar, it was put
+            // into, so it's created again and put into the new bar, if there
+            // is one
+            Event::DestroyNotify(e) => {
+                let icon_window = &mut self.monitor.bar.icon_window;
+
+                if let Some(icon_window) =
+                    icon_window.take_if(|x| x.window == e.window)
+                {
+                    // TODO: add logging
+                    x_context.free_icon_resources(&icon_window).ok();
+                    self.display_icon();
+                } else if self.is_bar_window(e.window) {
+                    self.display_icon();
+                }
+            }
+
+            // Bar may be started after ixwindow or restarted, so the icon
+            // is shown above it, as soon as it appears
+            Event::CreateNotify(CreateNotifyEvent { window, .. })
+            | Event::MapNotify(MapNotifyEvent { window, .. })
+                if !self.is_bar_window(*window) && self.is_new_bar(*window) =>
+            {
+                self.display_icon();
+            }
+
+            // Bar is hidden or restacked, for example by `wm-restack`
+            Event::UnmapNotify(UnmapNotifyEvent { window, .. })
+            | Event::ConfigureNotify(ConfigureNotifyEvent { window, .. })
+                if self.is_bar_window(*window) =>
+            {
+                self.display_icon();
+            }
+
+            Event::ButtonPress(e) => {
+                let icon_window = &self.monitor.bar.icon_window;
 
-            bar.icon = None;
+                if icon_window.as_ref().is_some_and(|x| x.window == e.event) {
+                    self.process_click(e.detail);
+                }
+            }
+
+            // Only the last event of the series is handled, because the whole
+            // icon is repainted anyway
+            Event::Expose(e) if e.count == 0 => {
+                if let Some(icon_window) = &self.monitor.bar.icon_window {
+                    if icon_window.window == e.window {
+                        // TODO: add logging
+                        x_context.repaint_icon(icon_window).ok();
+                    }
+                }
+            }
+
+            _ => {}
         }
     }
 
-    fn stop_watch_and_print_info(&self) {
-        if let Some(controller) = &self.monitor.bar.info_controller {
-            controller.send(Signal::Stop).unwrap();
+    // Runs the action, which is configured for the button, on the window,
+    // which icon is shown
+    #[cfg(feature = "x11")]
+    fn process_click(&mut self, button: u8) {
+        let Some(x_context) = self.x_context.clone() else {
+            return;
+        };
+
+        let Some(window) = &self.monitor.bar.state.curr_window else {
+            return;
+        };
+
+        let window_id = window.id;
+        let action = self.config.actions().get(button).clone();
+
+        let response = match action {
+            Action::None => Ok(()),
+            Action::Focus => {
+                self.wm_connection.focus_window(Some(&x_context), window_id)
+            }
+            Action::Close => x_context.close_window(window_id),
+            Action::Minimize => x_context.minimize_window(window_id),
+            Action::ToggleFullscreen => x_context.toggle_fullscreen(window_id),
+            Action::FocusNext => self.cycle_focus(1),
+            Action::FocusPrevious => self.cycle_focus(-1),
+            Action::Command(command) => {
+                let window_class =
+                    x_context.get_wm_class(window_id).unwrap_or_default();
+
+                spawn_command(
+                    &command,
+                    window_id,
+                    &window_class,
+                    &self.monitor.name,
+                );
+                Ok(())
+            }
+        };
+
+        if let Err(e) = response {
+            eprintln!("Couldn't run action for button {button}: {e}");
         }
     }
 
-    // fn drop_window(&mut self) {
-    //     self.stop_watch_and_print_info();
-    //     self.destroy_icon();
-    // }
+    // Focuses the window, which is the given number of windows away from the
+    // focused one on the current desktop
+    #[cfg(feature = "x11")]
+    fn cycle_focus(&mut self, step: isize) -> anyhow::Result<()> {
+        let Some(desktop_id) = self.get_focused_desktop_id() else {
+            return Ok(());
+        };
 
-    fn display_icon(&mut self) {
+        let x_context = self.x_context.clone();
+        let windows = self
+            .wm_connection
+            .get_desktop_windows(x_context.as_deref(), desktop_id);
+
+        if windows.is_empty() {
+            return Ok(());
+        }
+
+        let focused = self
+            .get_focused_window_id()
+            .and_then(|id| windows.iter().position(|&x| x == id));
+
+        let next = match focused {
+            Some(i) => (i as isize + step).rem_euclid(windows.len() as isize),
+            None => 0,
+        };
+
+        self.wm_connection
+            .focus_window(x_context.as_deref(), windows[next as usize])
+    }
+
+    #[cfg(feature = "x11")]
+    fn is_bar_window(&self, window: u32) -> bool {
+        self.monitor.bar.bar_windows.contains(&window)
+    }
+
+    // Checks, if the window is the bar on our monitor, which icon is not
+    // stacked above yet
+    #[cfg(feature = "x11")]
+    fn is_new_bar(&self, window: u32) -> bool {
+        let Some(x_context) = &self.x_context else {
+            return false;
+        };
+
+        // Icon is not shown, so there is nothing to restack
+        if self.monitor.bar.icon.is_none() {
+            return false;
+        }
+
+        x_context
+            .get_bar(window, self.config.bars(), &self.monitor.name)
+            .is_ok_and(|x| x.is_some())
+    }
+
+    pub fn update_window_info(&mut self) {
+        let Some(window) = &self.monitor.bar.state.curr_window else {
+            return;
+        };
+
+        let info_types = &self.config.print_info_settings().info_types;
+
+        // TODO: add logging
+        if let Some(window_info) = self.wm_connection.get_window_info(
+            self.x_context.as_deref(),
+            window.id,
+            info_types,
+        ) {
+            self.monitor.bar.info = Info::WindowInfo(window_info);
+            self.print_info();
+        }
+    }
+
+    // Prints info only if the output has changed since the last time
+    fn print_info(&mut self) {
         let bar = &mut self.monitor.bar;
+        let output = bar.info.output(&self.config);
 
-        if let Some(icon) = bar.icon.as_mut() {
-            if !icon.visible {
-                return;
+        if bar.output.as_ref() != Some(&output) {
+            match &self.daemon {
+                Some(daemon) => daemon.publish(&self.monitor.name, &output),
+                None => println!("{output}"),
             }
 
-            if icon.path.is_file() {
-                // TODO: add logging if couldn't display icon
-                if let Ok(new_icon_id) = x11_utils::display_icon(
-                    &self.x11rb_connection,
-                    &icon.path,
-                    icon.x,
-                    icon.y,
-                    icon.size,
-                    &self.monitor.name,
-                ) {
-                    icon.id = new_icon_id;
-                }
+            bar.output = Some(output);
+        }
+    }
+
+    fn update_scale(&mut self) {
+        let Some(x_context) = &self.x_context else {
+            return;
+        };
+
+        let name = &self.monitor.name;
+
+        self.monitor.scale = match self.config.scale() {
+            Scale::Fixed(scale) => *scale,
+            Scale::PerMonitor(scales) => {
+                scales.get(name).copied().unwrap_or(1.0)
+            }
+            Scale::Auto(source) => {
+                let dpi = match source {
+                    ScaleSource::Xft => x_context.get_xft_dpi(),
+                    ScaleSource::Randr => x_context.get_monitor_dpi(name),
+                };
+
+                // Detected factor is rounded to halves, because icons look
+                // blurry at scales like 1.1, and 96 DPI is the scale of 1
+                // TODO: add logging if couldn't detect DPI
+                dpi.map(|x| ((x / 48.0).round() / 2.0).max(1.0))
+                    .unwrap_or(1.0)
+            }
+        };
+    }
+
+    fn scaled(&self, value: f32) -> f32 {
+        (value * self.monitor.scale).round()
+    }
+
+    fn hide_icon(&mut self) {
+        let bar = &mut self.monitor.bar;
+
+        if let (Some(x_context), Some(icon_window)) =
+            (&self.x_context, bar.icon_window.as_mut())
+        {
+            // TODO: add logging
+            // If couldn't hide, don't do anything
+            x_context.hide_icon(icon_window).ok();
+        }
+
+        bar.icon = None;
+    }
+
+    // Subscribes to the changes of properties of the focused window, so that
+    // its title can be updated, and unsubscribes from the previous one
+    fn watch_window_properties(&self) {
+        let Some(x_context) = &self.x_context else {
+            return;
+        };
+
+        let state = &self.monitor.bar.state;
+        let prev_id = state.prev_window.as_ref().map(|x| x.id);
+        let curr_id = state.curr_window.as_ref().map(|x| x.id);
+
+        if prev_id == curr_id {
+            return;
+        }
+
+        // Previous window might be already destroyed, so we ignore errors
+        if let Some(id) = prev_id {
+            x_context.set_property_events(id, false).ok();
+        }
+
+        if let Some(id) = curr_id {
+            x_context.set_property_events(id, true).ok();
+        }
+    }
+
+    fn display_icon(&mut self) {
+        let bar = &self.monitor.bar;
+
+        let Some(x_context) = self.x_context.clone() else {
+            return;
+        };
+
+        let Some(icon) = bar.icon.clone() else {
+            return;
+        };
+
+        if !icon.visible || !icon.path.is_file() {
+            self.hide_icon();
+            return;
+        }
+
+        // TODO: add logging if couldn't find bars
+        let bars = x_context
+            .get_bars(self.config.bars(), &self.monitor.name)
+            .unwrap_or_default();
+        self.monitor.bar.bar_windows = bars.iter().map(|x| x.window).collect();
+
+        // Icon is hidden, until the bar appears on the monitor. It's kept, so
+        // that it's shown again, as soon as the bar is started
+        if bars.is_empty() {
+            if let Some(icon_window) = self.monitor.bar.icon_window.as_mut() {
+                // TODO: add logging
+                x_context.hide_icon(icon_window).ok();
             }
+
+            return;
+        }
+
+        let icon_window = match self.monitor.bar.icon_window.take() {
+            Some(x) => Some(x),
+            None => x_context.create_icon_window(self.icon_style).ok(),
+        };
+
+        // TODO: add logging if couldn't create icon window
+        let Some(mut icon_window) = icon_window else {
+            return;
+        };
+
+        let response = x_context.display_icon(
+            &mut icon_window,
+            &icon.path,
+            icon.x,
+            icon.y,
+            icon.size,
+            &self.monitor.name,
+            &bars,
+            self.config.reparent(),
+            self.icon_style,
+            self.config.color(),
+        );
+
+        self.monitor.bar.icon_window = Some(icon_window);
+
+        // TODO: add logging if couldn't display icon
+        if response.is_err() {
+            self.hide_icon();
         }
     }
 
@@ -250,13 +783,15 @@ where
     fn new_window(&self, window_id: u32) -> Window {
         let window_name = self
             .wm_connection
-            .get_window_name(window_id)
-            .unwrap_or(String::new());
+            .get_window_name(self.x_context.as_deref(), window_id)
+            .unwrap_or_default();
 
         Window {
             id: window_id,
             name: window_name,
-            fullscreen: self.wm_connection.is_window_fullscreen(window_id),
+            fullscreen: self
+                .wm_connection
+                .is_window_fullscreen(self.x_context.as_deref(), window_id),
         }
     }
 
@@ -267,7 +802,7 @@ where
 
         if let Some(desktop) = current_desktop {
             self.wm_connection
-                .get_fullscreen_window_id(desktop)
+                .get_fullscreen_window_id(self.x_context.as_deref(), desktop)
                 .is_some()
         } else {
             false
@@ -281,29 +816,110 @@ where
     fn gen_icon_name(&self, window_id: u32) -> String {
         // TODO: add logging in case of no window name
         self.wm_connection
-            .get_window_name(window_id)
-            .unwrap_or(String::new())
+            .get_window_name(self.x_context.as_deref(), window_id)
+            .unwrap_or_default()
+    }
+
+    // Generated and custom icons don't depend on the scale of the monitor,
+    // because they are resized to the size of the icon, when they are
+    // displayed. Icons from the theme are looked up for the given size
+    fn gen_icon_path(&self, window_id: u32, size: u16) -> PathBuf {
+        let cache_dir = self.config.cache_dir();
+        let icon_name = self.gen_icon_name(window_id);
+        let extension = self.icon_style.extension();
+
+        // Custom icons can be put into cache directory in any of these
+        // formats. Svg ones are preferred, because they are rendered at
+        // exactly the size of the icon
+        for ext in ["svg", "png", extension] {
+            let path = cache_dir.join(format!("{icon_name}.{ext}"));
+
+            if path.is_file() {
+                return path;
+            }
+        }
+
+        let theme_path = self.theme_icon_path(&icon_name, size);
+
+        if theme_path.is_file() {
+            return theme_path;
+        }
+
+        cache_dir.join(format!("{icon_name}.{extension}"))
+    }
+
+    // Icons from the icon theme are kept apart from the generated ones, so
+    // that they are replaced, once the window sets _NET_WM_ICON
+    fn theme_icon_dir(&self) -> PathBuf {
+        self.config.cache_dir().join("icon-theme")
+    }
+
+    // Icon, that was taken from the icon theme or wasn't found at all, is
+    // replaced, when the window sets _NET_WM_ICON
+    #[cfg(feature = "x11")]
+    fn is_icon_replaceable(&self) -> bool {
+        self.monitor.bar.icon.as_ref().is_none_or(|x| {
+            !x.path.is_file() || x.path.starts_with(self.theme_icon_dir())
+        })
+    }
+
+    // Icon theme has raster icons of different sizes, so they are kept
+    // apart for every size, and HiDPI monitors don't get upscaled ones
+    fn theme_icon_size_dir(&self, size: u16) -> PathBuf {
+        self.theme_icon_dir().join(size.to_string())
+    }
+
+    fn theme_icon_path(&self, icon_name: &str, size: u16) -> PathBuf {
+        let theme_dir = self.theme_icon_size_dir(size);
+
+        // Svg icons are copied from the theme as is
+        let svg_path = theme_dir.join(format!("{icon_name}.svg"));
+
+        if svg_path.is_file() {
+            return svg_path;
+        }
+
+        theme_dir.join(format!("{icon_name}.{}", self.icon_style.extension()))
     }
 
-    fn gen_icon_path(&self, window_id: u32) -> PathBuf {
-        PathBuf::from(format!(
-            "{}/{}.jpg",
-            self.config.cache_dir().to_string_lossy(),
-            self.gen_icon_name(window_id)
-        ))
+    // Returns x and y of the icon relative to the monitor and its size. `x`
+    // is passed from outside, because some backends shift it
+    fn icon_geometry(&self, x: f32) -> (i16, i16, u16) {
+        let (x, y) = (self.scaled(x), self.scaled(self.config.y() as f32));
+        let size = self.scaled(self.config.size() as f32);
+        let absolute = (x as i16, y as i16, size as u16);
+
+        let (Placement::Bar, Some(x_context), Some(monitor_geometry)) = (
+            self.config.placement(),
+            &self.x_context,
+            self.monitor.geometry,
+        ) else {
+            return absolute;
+        };
+
+        // TODO: add logging, if bar wasn't found
+        let Ok((bar_x, bar_y, _, bar_height)) =
+            x_context.get_bar_geometry(self.config.bars(), &self.monitor.name)
+        else {
+            return absolute;
+        };
+
+        let (monitor_x, monitor_y, _, _) = monitor_geometry;
+        let size = (bar_height as f32 - 2.0 * y).max(1.0);
+
+        (
+            bar_x - monitor_x + x as i16,
+            bar_y - monitor_y + y as i16,
+            size as u16,
+        )
     }
 
     fn new_icon(&mut self, window_id: u32) -> Icon {
-        let x = self.config.x();
-        let y = self.config.y();
-        let size = self.config.size();
-        let icon_path = self.gen_icon_path(window_id);
+        let (x, y, size) = self.icon_geometry(self.config.x() as f32);
+        let icon_path = self.gen_icon_path(window_id, size);
 
-        // It's okay to put 0 for id here, because it will be changed when
-        // displaying the icon
         Icon {
             path: icon_path,
-            id: 0,
             x,
             y,
             size,
@@ -312,11 +928,26 @@ where
     }
 
     fn update_icon(&mut self, window_id: u32) {
-        let icon = self.new_icon(window_id);
+        // Icons can be drawn only on X server
+        if self.x_context.is_none() {
+            return;
+        }
+
+        let mut icon = self.new_icon(window_id);
+
+        // Apps like Electron ones set _NET_WM_ICON some time after the window
+        // is shown, so the icon, found in the icon theme before, is replaced
+        if icon.path.starts_with(self.theme_icon_dir()) {
+            self.generate_icon(window_id).ok();
+            icon.path = self.gen_icon_path(window_id, icon.size);
+        }
 
         if !icon.path.is_file() {
-            self.try_generate_icon(window_id);
+            self.try_generate_icon(window_id, icon.size);
             thread::sleep(Duration::from_millis(100)); // let icon be generated
+
+            // Icon could be found in the icon theme, which is kept elsewhere
+            icon.path = self.gen_icon_path(window_id, icon.size);
         }
 
         self.monitor.bar.icon = Some(icon);
@@ -324,52 +955,93 @@ where
         self.display_icon();
     }
 
-    fn try_generate_icon(&self, window_id: u32) {
-        if !self.config.cache_dir().is_dir() {
-            fs::create_dir_all(self.config.cache_dir())
+    fn try_generate_icon(&self, window_id: u32, size: u16) {
+        let Some(x_context) = self.x_context.clone() else {
+            return;
+        };
+
+        let cache_dir = self.config.cache_dir().to_path_buf();
+
+        if !cache_dir.is_dir() {
+            fs::create_dir_all(&cache_dir)
                 .expect("Failed to create nonexisting cache directory");
         }
 
         let config = self.config.clone();
         let icon_name = self.gen_icon_name(window_id);
-        let icon_path = self.gen_icon_path(window_id);
+        let icon_path = self.gen_icon_path(window_id, size);
+        let icon_style = self.icon_style;
+        let theme_dir = self.theme_icon_size_dir(size);
 
         thread::spawn(move || {
             let mut timeout = 3000;
             let mut response;
+            let mut theme_checked = false;
 
             while timeout > 0 && !icon_path.is_file() {
-                response = x11_utils::generate_icon(
+                response = x_context.generate_icon(
                     &icon_name,
-                    config.cache_dir(),
+                    &cache_dir,
                     config.color(),
                     window_id,
+                    icon_style,
                 );
 
                 if response.is_ok() {
                     break;
                 }
 
+                // Some apps never set _NET_WM_ICON, so we try to find their
+                // icon in the icon theme, but only once, because it requires
+                // reading a lot of files
+                if !theme_checked {
+                    theme_checked = true;
+                    response = fs::create_dir_all(&theme_dir)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|_| {
+                            x_context.generate_icon_from_theme(
+                                &icon_name,
+                                &theme_dir,
+                                config.color(),
+                                window_id,
+                                icon_style,
+                                size,
+                            )
+                        });
+
+                    if response.is_ok() {
+                        break;
+                    }
+                }
+
                 thread::sleep(Duration::from_millis(100));
                 timeout -= 100;
             }
         });
     }
 
+    // Generates icon from _NET_WM_ICON or WM_HINTS of the window right away
+    fn generate_icon(&self, window_id: u32) -> anyhow::Result<()> {
+        let Some(x_context) = &self.x_context else {
+            return Ok(());
+        };
+
+        x_context.generate_icon(
+            &self.gen_icon_name(window_id),
+            self.config.cache_dir(),
+            self.config.color(),
+            window_id,
+            self.icon_style,
+        )
+    }
+
     pub fn process_focused_window(&mut self, window_id: u32) {
         let window = self.new_window(window_id);
         self.monitor.bar.state.update_window(&window);
-
-        if self.monitor.bar.state.prev_window.is_some() {
-            self.stop_watch_and_print_info();
-        }
-
-        let (info_sender, info_receiver) = mpsc::channel();
-        let info = Info::WindowInfo(WindowInfo::default()); // Real info will be set later
+        self.watch_window_properties();
+        self.update_window_info();
 
         let bar = &mut self.monitor.bar;
-        bar.info = info;
-        bar.info_controller = Some(info_sender);
 
         if bar.state.prev_window.is_none() {
             self.update_icon(window_id);
@@ -377,30 +1049,76 @@ where
             let prev_window = bar.state.prev_window.clone().unwrap();
             let curr_window = bar.state.curr_window.clone().unwrap();
 
+            // Icon window is updated in place, so the old icon stays visible
+            // until the new one is drawn
             // TODO: think through HANDLE fullscreen toggle of the same app
             if prev_window.name == curr_window.name {
                 if prev_window.fullscreen && !curr_window.fullscreen {
-                    self.destroy_icon();
                     self.update_icon(window_id);
                 }
 
                 if !prev_window.fullscreen && curr_window.fullscreen {
-                    self.destroy_icon();
+                    self.hide_icon();
                 }
             } else {
-                self.destroy_icon();
                 self.update_icon(window_id);
             }
         }
 
         // println!("icon: {:#?}", self.monitor.bar.icon);
+    }
+
+    // Handles outputs being connected, disconnected, moved or rotated. There
+    // are several events for one change, so only the first of them, which
+    // changes geometry of our monitor, is processed
+    #[cfg(feature = "x11")]
+    pub fn process_monitor_change(&mut self) {
+        let Some(x_context) = self.x_context.clone() else {
+            return;
+        };
+
+        if self.monitor.follows_primary {
+            if let Ok(name) = x_context.get_primary_monitor_name() {
+                self.monitor.name = name;
+            }
+        }
+
+        let geometry = x_context.get_monitor_geometry(&self.monitor.name).ok();
+
+        if geometry == self.monitor.geometry {
+            return;
+        }
+
+        self.monitor.geometry = geometry;
+
+        // Output is disconnected, so there is no bar to draw icon on
+        if geometry.is_none() {
+            if let Some(icon_window) = self.monitor.bar.icon_window.as_mut() {
+                // TODO: add logging
+                x_context.hide_icon(icon_window).ok();
+            }
+
+            return;
+        }
+
+        // Monitor may be replaced by the one with another DPI
+        self.update_scale();
 
-        self.watch_and_print_info(info_receiver);
+        // State is reset, so that icon is redrawn at the new position even
+        // if the focused window is the same
+        self.monitor.bar.state.update_empty();
+        self.watch_window_properties();
+
+        match self.get_focused_window_id() {
+            Some(id) => self.process_focused_window(id),
+            None => self.process_empty_desktop(),
+        }
     }
 
     // TODO: think through
+    #[cfg(feature = "x11")]
     pub fn process_fullscreen_window(&mut self) {
-        self.destroy_icon();
+        self.hide_icon();
     }
 
     fn set_empty_info(&mut self) {
@@ -411,16 +1129,17 @@ where
 
     pub fn process_empty_desktop(&mut self) {
         self.monitor.bar.state.update_empty();
+        self.watch_window_properties();
 
         if self.monitor.bar.state.prev_window.is_some() {
-            self.stop_watch_and_print_info();
-            self.destroy_icon();
+            self.hide_icon();
         }
 
         self.set_empty_info();
-        self.monitor.bar.info.print(&self.config);
+        self.print_info();
     }
 
+    #[cfg(feature = "x11")]
     pub fn get_focused_desktop_id(&mut self) -> Option<u32> {
         self.wm_connection
             .get_focused_desktop_id(&self.monitor.name)
@@ -430,13 +1149,92 @@ where
         self.wm_connection.get_focused_window_id(&self.monitor.name)
     }
 
+    #[cfg(feature = "x11")]
     pub fn get_fullscreen_window_id(&mut self, desktop_id: u32) -> Option<u32> {
-        self.wm_connection.get_fullscreen_window_id(desktop_id)
+        self.wm_connection
+            .get_fullscreen_window_id(self.x_context.as_deref(), desktop_id)
     }
 
+    // Only i3, bspwm and leftwm backends check, if desktop is empty, the
+    // others just look for the focused window
+    #[allow(dead_code)]
     pub fn is_desk_empty(&mut self, desktop_id: u32) -> bool {
         self.wm_connection.is_desk_empty(desktop_id)
     }
+
+    #[cfg(feature = "ewmh")]
+    pub fn x_context(&self) -> Option<&XContext> {
+        self.x_context.as_deref()
+    }
+
+    #[cfg(feature = "leftwm")]
+    pub fn wm_connection(&mut self) -> &mut W {
+        &mut self.wm_connection
+    }
+}
+
+impl<W, C> Drop for WmCore<W, C>
+where
+    W: WmConnection,
+    C: Config,
+{
+    fn drop(&mut self) {
+        let Some(x_context) = &self.x_context else {
+            return;
+        };
+
+        if let Some(icon_window) = &self.monitor.bar.icon_window {
+            x_context.destroy_icon(icon_window).ok();
+        }
+
+        // Other monitors may still watch the window
+        if let Some(window) = &self.monitor.bar.state.curr_window {
+            x_context.set_property_events(window.id, false).ok();
+        }
+    }
+}
+
+// Backends of X11 window managers can't work without X server
+#[cfg(feature = "x11")]
+fn require_x_context(
+    x_context: Option<Arc<XContext>>,
+) -> anyhow::Result<Arc<XContext>> {
+    x_context.context("Couldn't connect to the X server")
+}
+
+// Icon style, that is used when there is no compositor running
+fn fallback_icon_style(config: &impl Config) -> IconStyle {
+    match config.icon_mode() {
+        IconMode::Fill => IconStyle::Filled,
+        IconMode::Shape => IconStyle::Shaped {
+            alpha_threshold: config.alpha_threshold(),
+        },
+        IconMode::Sample => IconStyle::Sampled,
+    }
+}
+
+// Runs the command in the shell without waiting for it, so that the icon
+// keeps being updated
+#[cfg(feature = "x11")]
+fn spawn_command(
+    command: &str,
+    window_id: u32,
+    window_class: &str,
+    monitor_name: &str,
+) {
+    let mut shell = Command::new("sh");
+    shell
+        .arg("-c")
+        .arg(command)
+        .env("IXWINDOW_WINDOW_ID", window_id.to_string())
+        .env("IXWINDOW_WINDOW_CLASS", window_class)
+        .env("IXWINDOW_MONITOR", monitor_name);
+
+    thread::spawn(move || {
+        if let Err(e) = shell.status() {
+            eprintln!("Couldn't run command: {e}");
+        }
+    });
 }
 
 pub trait WmCoreFeatures<W, C>
@@ -444,25 +1242,45 @@ where
     W: WmConnection,
     C: Config,
 {
-    fn init(monitor_name: Option<&str>, config: Option<&Path>) -> WmCore<W, C>;
+    fn init(
+        x_context: Option<Arc<XContext>>,
+        monitor_name: Option<&str>,
+        config: Option<&Path>,
+    ) -> anyhow::Result<WmCore<W, C>>;
+
+    // Returns names of all monitors, which are served by daemon
+    fn monitor_names(x_context: Option<&XContext>) -> Vec<String> {
+        x_context
+            .and_then(|x| x.get_monitor_names().ok())
+            .unwrap_or_default()
+    }
 
     fn update_icon_position(&mut self);
 }
 
+#[cfg(feature = "i3")]
 impl WmCoreFeatures<I3Connection, I3Config> for WmCore<I3Connection, I3Config> {
-    fn init(monitor_name: Option<&str>, config_file: Option<&Path>) -> Self {
+    fn init(
+        x_context: Option<Arc<XContext>>,
+        monitor_name: Option<&str>,
+        config_file: Option<&Path>,
+    ) -> anyhow::Result<Self> {
         let wm_connection =
-            I3Connection::connect().expect("Failed to connect to i3");
-        let config = config::load_i3(config_file);
-        let monitor = Monitor::init(monitor_name);
-        let (x11rb_connection, _) = x11rb::connect(None).unwrap();
-
-        Self {
+            I3Connection::connect().context("Failed to connect to i3")?;
+        let config = config::load_i3(config_file)?;
+        let x_context = require_x_context(x_context)?;
+        let monitor = Monitor::init(&x_context, monitor_name)?;
+        let icon_style =
+            x_context.detect_icon_style(fallback_icon_style(&config));
+
+        Ok(Self {
             config,
             wm_connection,
             monitor,
-            x11rb_connection,
-        }
+            x_context: Some(x_context),
+            icon_style,
+            daemon: None,
+        })
     }
 
     fn update_icon_position(&mut self) {
@@ -471,30 +1289,173 @@ impl WmCoreFeatures<I3Connection, I3Config> for WmCore<I3Connection, I3Config> {
             &mut self.wm_connection,
             &self.monitor.name,
         );
+        let (x, _, _) = self.icon_geometry(
+            (config.x() as f32) + config.gap_per_desk * (desks_num as f32),
+        );
 
         if let Some(icon) = self.monitor.bar.icon.as_mut() {
-            icon.x = ((config.x() as f32)
-                + config.gap_per_desk * (desks_num as f32))
-                as i16;
+            icon.x = x;
         }
     }
 }
 
+#[cfg(feature = "bspwm")]
 impl WmCoreFeatures<BspwmConnection, BspwmConfig>
     for WmCore<BspwmConnection, BspwmConfig>
 {
-    fn init(monitor_name: Option<&str>, config_file: Option<&Path>) -> Self {
+    fn init(
+        x_context: Option<Arc<XContext>>,
+        monitor_name: Option<&str>,
+        config_file: Option<&Path>,
+    ) -> anyhow::Result<Self> {
         let wm_connection = BspwmConnection::new();
-        let config = config::load_bspwm(config_file);
-        let monitor = Monitor::init(monitor_name);
-        let (x11rb_connection, _) = x11rb::connect(None).unwrap();
+        let config = config::load_bspwm(config_file)?;
+        let x_context = require_x_context(x_context)?;
+        let monitor = Monitor::init(&x_context, monitor_name)?;
+        let icon_style =
+            x_context.detect_icon_style(fallback_icon_style(&config));
 
-        Self {
+        Ok(Self {
             config,
             wm_connection,
             monitor,
-            x11rb_connection,
-        }
+            x_context: Some(x_context),
+            icon_style,
+            daemon: None,
+        })
+    }
+
+    fn update_icon_position(&mut self) {}
+}
+
+#[cfg(feature = "ewmh")]
+impl WmCoreFeatures<EwmhConnection, EwmhConfig>
+    for WmCore<EwmhConnection, EwmhConfig>
+{
+    fn init(
+        x_context: Option<Arc<XContext>>,
+        monitor_name: Option<&str>,
+        config_file: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        // Connection to the window manager is the connection to the X server
+        // here, so they share the same context
+        let x_context = require_x_context(x_context)?;
+        let wm_connection = EwmhConnection::new(Arc::clone(&x_context))?;
+        let config = config::load_ewmh(config_file)?;
+        let monitor = Monitor::init(&x_context, monitor_name)?;
+        let icon_style =
+            x_context.detect_icon_style(fallback_icon_style(&config));
+
+        Ok(Self {
+            config,
+            wm_connection,
+            monitor,
+            x_context: Some(x_context),
+            icon_style,
+            daemon: None,
+        })
+    }
+
+    fn update_icon_position(&mut self) {}
+}
+
+#[cfg(feature = "herbstluftwm")]
+impl WmCoreFeatures<HerbstluftwmConnection, HerbstluftwmConfig>
+    for WmCore<HerbstluftwmConnection, HerbstluftwmConfig>
+{
+    fn init(
+        x_context: Option<Arc<XContext>>,
+        monitor_name: Option<&str>,
+        config_file: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        // X context is needed to match herbstluftwm monitors with RandR ones
+        let x_context = require_x_context(x_context)?;
+        let wm_connection = HerbstluftwmConnection::new(Arc::clone(&x_context));
+        let config = config::load_herbstluftwm(config_file)?;
+        let monitor = Monitor::init(&x_context, monitor_name)?;
+        let icon_style =
+            x_context.detect_icon_style(fallback_icon_style(&config));
+
+        Ok(Self {
+            config,
+            wm_connection,
+            monitor,
+            x_context: Some(x_context),
+            icon_style,
+            daemon: None,
+        })
+    }
+
+    fn update_icon_position(&mut self) {}
+}
+
+#[cfg(feature = "leftwm")]
+impl WmCoreFeatures<LeftwmConnection, LeftwmConfig>
+    for WmCore<LeftwmConnection, LeftwmConfig>
+{
+    fn init(
+        x_context: Option<Arc<XContext>>,
+        monitor_name: Option<&str>,
+        config_file: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        let x_context = require_x_context(x_context)?;
+        let wm_connection = LeftwmConnection::new(Arc::clone(&x_context))?;
+        let config = config::load_leftwm(config_file)?;
+        let monitor = Monitor::init(&x_context, monitor_name)?;
+        let icon_style =
+            x_context.detect_icon_style(fallback_icon_style(&config));
+
+        Ok(Self {
+            config,
+            wm_connection,
+            monitor,
+            x_context: Some(x_context),
+            icon_style,
+            daemon: None,
+        })
+    }
+
+    fn update_icon_position(&mut self) {}
+}
+
+// There is no X server on Wayland, so icon is never drawn and only the
+// information about the window is printed
+#[cfg(feature = "sway")]
+impl WmCoreFeatures<SwayConnection, SwayConfig>
+    for WmCore<SwayConnection, SwayConfig>
+{
+    fn init(
+        _x_context: Option<Arc<XContext>>,
+        monitor_name: Option<&str>,
+        config_file: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        let wm_connection =
+            SwayConnection::connect().context("Couldn't connect to sway")?;
+        let config = config::load_sway(config_file)?;
+        let name = match monitor_name {
+            Some(x) => x.to_string(),
+            None => sway::get_focused_output(&wm_connection)
+                .context("Couldn't get name of the focused output")?,
+        };
+
+        // Icon is never drawn, but the style is taken from the config like
+        // in the other backends
+        let icon_style = fallback_icon_style(&config);
+
+        Ok(Self {
+            config,
+            wm_connection,
+            monitor: Monitor::with_name(name),
+            x_context: None,
+            icon_style,
+            daemon: None,
+        })
+    }
+
+    fn monitor_names(_x_context: Option<&XContext>) -> Vec<String> {
+        SwayConnection::connect()
+            .and_then(|x| x.get_outputs())
+            .unwrap_or_default()
     }
 
     fn update_icon_position(&mut self) {}
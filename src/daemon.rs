@@ -0,0 +1,144 @@
+use anyhow::bail;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Clients of one monitor and the last output, which is sent to the new ones
+// right away, so they don't have to wait for the next event
+#[derive(Debug, Default)]
+struct Subscribers {
+    last_output: Option<String>,
+    streams: Vec<UnixStream>,
+}
+
+// Serves output of every monitor to `ixwindow listen` clients. Client sends
+// name of the monitor on the first line and then receives one line per
+// update
+#[derive(Debug, Default)]
+pub struct Server {
+    monitors: Mutex<HashMap<String, Subscribers>>,
+}
+
+impl Server {
+    pub fn bind() -> anyhow::Result<Arc<Self>> {
+        let socket_path = socket_path();
+
+        if socket_path.exists() {
+            if UnixStream::connect(&socket_path).is_ok() {
+                bail!("Daemon is already running");
+            }
+
+            // Left after the previous daemon
+            fs::remove_file(&socket_path)?;
+        }
+
+        let listener = UnixListener::bind(&socket_path)?;
+        let server = Arc::new(Self::default());
+        let server_clone = Arc::clone(&server);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream
+                    .map_err(anyhow::Error::from)
+                    .and_then(|x| server_clone.subscribe(x));
+
+                if let Err(e) = result {
+                    eprintln!("Couldn't accept client: {e}");
+                }
+            }
+        });
+
+        Ok(server)
+    }
+
+    fn subscribe(&self, stream: UnixStream) -> anyhow::Result<()> {
+        // Client, that doesn't send monitor name or doesn't read its output,
+        // shouldn't block the others
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+
+        let mut monitor_name = String::new();
+        BufReader::new(&stream).read_line(&mut monitor_name)?;
+
+        let mut monitors = self.monitors.lock().unwrap();
+        let subscribers =
+            monitors.entry(monitor_name.trim().to_string()).or_default();
+
+        if let Some(output) = &subscribers.last_output {
+            writeln!(&stream, "{output}")?;
+        }
+
+        subscribers.streams.push(stream);
+
+        Ok(())
+    }
+
+    pub fn publish(&self, monitor_name: &str, output: &str) {
+        let mut monitors = self.monitors.lock().unwrap();
+        let subscribers = monitors.entry(monitor_name.to_string()).or_default();
+
+        // Clients, that were closed, are dropped here
+        subscribers
+            .streams
+            .retain(|mut stream| writeln!(stream, "{output}").is_ok());
+        subscribers.last_output = Some(output.to_string());
+    }
+}
+
+pub fn socket_path() -> PathBuf {
+    let runtime_dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(x) if !x.is_empty() => PathBuf::from(x),
+        _ => env::temp_dir(),
+    };
+
+    runtime_dir.join("ixwindow.sock")
+}
+
+// Prints output of the given monitor, which is received from the daemon
+pub fn listen(monitor_name: &str) -> anyhow::Result<()> {
+    let Ok(stream) = UnixStream::connect(socket_path()) else {
+        bail!("Daemon is not running, start it with `ixwindow daemon`");
+    };
+
+    writeln!(&stream, "{monitor_name}")?;
+
+    let mut stdout = io::stdout().lock();
+
+    for line in BufReader::new(stream).lines() {
+        writeln!(stdout, "{}", line?)?;
+        stdout.flush()?;
+    }
+
+    bail!("Daemon has exited")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_publish_works() {
+        let server = Server::default();
+        server.publish("eDP-1", "Alacritty");
+
+        let (client, stream) = UnixStream::pair().unwrap();
+        writeln!(&client, "eDP-1").unwrap();
+        server.subscribe(stream).unwrap();
+
+        server.publish("HDMI-1", "Firefox");
+        server.publish("eDP-1", "Empty");
+        drop(server);
+
+        let lines: Vec<_> =
+            BufReader::new(client).lines().map(|x| x.unwrap()).collect();
+
+        assert_eq!(lines, ["Alacritty", "Empty"]);
+    }
+}
@@ -0,0 +1,274 @@
+use anyhow::Context as _;
+
+use std::convert::Infallible;
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt as _, Window};
+use x11rb::protocol::Event;
+
+use crate::config::EwmhConfig;
+use crate::core::{CoreEvent, Target, WmCore};
+use crate::wm_connection::WmConnection;
+use crate::x11_utils::XContext;
+
+// Value of _NET_WM_DESKTOP for windows, that are shown on all desktops
+const ALL_DESKTOPS: u32 = 0xFFFFFFFF;
+
+// Connection to any window manager, which supports EWMH. All the information
+// is read from the properties of the root window and the client windows
+pub struct EwmhConnection {
+    x_context: Arc<XContext>,
+
+    // Window, that was focused on the monitor last
+    focused_window: Option<Window>,
+}
+
+impl EwmhConnection {
+    pub fn new(x_context: Arc<XContext>) -> anyhow::Result<Self> {
+        // Window manager updates properties of the root window, when focus or
+        // desktop changes, so we need to listen to their changes
+        x_context
+            .select_root_events()
+            .context("Couldn't subscribe to root window events")?;
+
+        Ok(Self {
+            x_context,
+            focused_window: None,
+        })
+    }
+
+    fn get_cardinals(&self, window: Window, atom: Atom) -> Vec<u32> {
+        self.get_property32(window, atom, AtomEnum::CARDINAL.into())
+    }
+
+    fn get_property32(
+        &self,
+        window: Window,
+        atom: Atom,
+        type_: Atom,
+    ) -> Vec<u32> {
+        let reply = self
+            .x_context
+            .conn
+            .get_property(false, window, atom, type_, 0, 1024)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok());
+
+        let Some(reply) = reply else {
+            return Vec::new();
+        };
+
+        let values = match reply.value32() {
+            Some(values) => values.collect(),
+            None => Vec::new(),
+        };
+
+        values
+    }
+
+    pub fn get_window_desktop(&self, window: Window) -> Option<u32> {
+        let atom = self.x_context.atoms._NET_WM_DESKTOP;
+
+        self.get_cardinals(window, atom).first().copied()
+    }
+
+    fn is_window_hidden(&self, window: Window) -> bool {
+        let atoms = &self.x_context.atoms;
+
+        self.get_property32(window, atoms._NET_WM_STATE, AtomEnum::ATOM.into())
+            .contains(&atoms._NET_WM_STATE_HIDDEN)
+    }
+
+    // Returns the window, which is focused on any of the monitors
+    pub fn get_active_window(&self) -> Option<Window> {
+        let root = self.x_context.screen().root;
+        let atom = self.x_context.atoms._NET_ACTIVE_WINDOW;
+        let window = self
+            .get_property32(root, atom, AtomEnum::WINDOW.into())
+            .first()
+            .copied();
+
+        // Window manager sets it to None (0), when no window is focused
+        window.filter(|&x| x != x11rb::NONE)
+    }
+
+    // Window belongs to the monitor, which contains its center
+    fn is_window_on_monitor(
+        &self,
+        window: Window,
+        monitor_geometry: (i16, i16, u16, u16),
+    ) -> bool {
+        let Ok((x, y, width, height)) =
+            self.x_context.get_window_geometry(window)
+        else {
+            return false;
+        };
+
+        let (monitor_x, monitor_y, monitor_width, monitor_height) =
+            monitor_geometry;
+        let (left, top) = (monitor_x as i32, monitor_y as i32);
+        let center_x = x as i32 + width as i32 / 2;
+        let center_y = y as i32 + height as i32 / 2;
+
+        (left..left + monitor_width as i32).contains(&center_x)
+            && (top..top + monitor_height as i32).contains(&center_y)
+    }
+
+    // Returns all visible windows, that are shown on the given desktop
+    pub fn get_visible_windows(&self, desktop_id: u32) -> Vec<Window> {
+        let atoms = &self.x_context.atoms;
+        let root = self.x_context.screen().root;
+        let clients = self.get_property32(
+            root,
+            atoms._NET_CLIENT_LIST,
+            AtomEnum::WINDOW.into(),
+        );
+
+        clients
+            .into_iter()
+            .filter(|&window| match self.get_window_desktop(window) {
+                Some(x) => x == desktop_id || x == ALL_DESKTOPS,
+                None => false,
+            })
+            .filter(|&window| !self.is_window_hidden(window))
+            .collect()
+    }
+}
+
+// EWMH doesn't tell, which monitor desktop or window belongs to, so desktops
+// are shared by all the monitors and windows are found by their geometry
+impl WmConnection for EwmhConnection {
+    fn get_focused_desktop_id(&mut self, _monitor_name: &str) -> Option<u32> {
+        let root = self.x_context.screen().root;
+        let atom = self.x_context.atoms._NET_CURRENT_DESKTOP;
+
+        self.get_cardinals(root, atom).first().copied()
+    }
+
+    fn is_desk_empty(&mut self, desktop_id: u32) -> bool {
+        self.get_visible_windows(desktop_id).is_empty()
+    }
+
+    fn get_focused_window_id(&mut self, monitor_name: &str) -> Option<u32> {
+        let active_window = self.get_active_window();
+
+        // Without geometry monitors can't be told apart
+        let Ok(geometry) = self.x_context.get_monitor_geometry(monitor_name)
+        else {
+            return active_window;
+        };
+
+        if let Some(window) =
+            active_window.filter(|&x| self.is_window_on_monitor(x, geometry))
+        {
+            self.focused_window = Some(window);
+            return Some(window);
+        }
+
+        // Focus is on the other monitor, so we keep showing the window, that
+        // was focused here last, if it's still there
+        let window = self.focused_window?;
+        let desktop_id = self.get_focused_desktop_id(monitor_name)?;
+
+        (self.get_visible_windows(desktop_id).contains(&window)
+            && self.is_window_on_monitor(window, geometry))
+        .then_some(window)
+    }
+
+    fn get_fullscreen_window_id(
+        &mut self,
+        x_context: Option<&XContext>,
+        desktop_id: u32,
+    ) -> Option<u32> {
+        self.get_visible_windows(desktop_id)
+            .into_iter()
+            .find(|&window| self.is_window_fullscreen(x_context, window))
+    }
+
+    fn get_desktops_number(&mut self, _monitor_name: &str) -> u32 {
+        let root = self.x_context.screen().root;
+        let atom = self.x_context.atoms._NET_NUMBER_OF_DESKTOPS;
+
+        self.get_cardinals(root, atom).first().copied().unwrap_or(0)
+    }
+
+    fn get_desktop_windows(
+        &mut self,
+        _x_context: Option<&XContext>,
+        desktop_id: u32,
+    ) -> Vec<u32> {
+        self.get_visible_windows(desktop_id)
+    }
+}
+
+pub fn exec(
+    x_context: XContext,
+    target: &Target,
+    config_file: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut cores = WmCore::init_all(Some(x_context), target, config_file)?;
+
+    for core in &mut cores {
+        core.process_start();
+    }
+
+    // All the events come from the X server, so there are no events from
+    // window manager itself
+    let (sender, receiver) = mpsc::channel::<CoreEvent<Infallible>>();
+    cores[0].listen_x11_events(sender);
+
+    WmCore::run(cores, receiver, target, config_file, |core, event| {
+        if let CoreEvent::X11(event) = event {
+            core.handle_event(event);
+        }
+    });
+
+    Ok(())
+}
+
+impl WmCore<EwmhConnection, EwmhConfig> {
+    fn handle_event(&mut self, event: &Event) {
+        let Event::PropertyNotify(e) = event else {
+            return;
+        };
+
+        let Some(x_context) = self.x_context() else {
+            return;
+        };
+
+        let atoms = x_context.atoms;
+        let root = x_context.screen().root;
+        let root_atoms = [
+            atoms._NET_ACTIVE_WINDOW,
+            atoms._NET_CURRENT_DESKTOP,
+            atoms._NET_CLIENT_LIST,
+        ];
+
+        if e.window == root && root_atoms.contains(&e.atom) {
+            self.handle_general_event();
+        }
+
+        // Focused window was toggled to or from fullscreen
+        if e.atom == atoms._NET_WM_STATE
+            && self.get_focused_window_id() == Some(e.window)
+        {
+            self.handle_general_event();
+        }
+    }
+
+    fn handle_general_event(&mut self) {
+        match self.get_focused_window_id() {
+            Some(id) => self.process_focused_window(id),
+            None => self.process_empty_desktop(),
+        }
+
+        // Desktop isn't checked for emptiness, because some window managers
+        // don't set _NET_WM_DESKTOP of the windows
+        if let Some(desktop) = self.get_focused_desktop_id() {
+            if self.get_fullscreen_window_id(desktop).is_some() {
+                self.process_fullscreen_window();
+            }
+        }
+    }
+}
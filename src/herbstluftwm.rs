@@ -0,0 +1,308 @@
+use anyhow::Context as _;
+
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use crate::config::HerbstluftwmConfig;
+use crate::core::{CoreEvent, Target, WmCore};
+use crate::wm_connection::WmConnection;
+use crate::x11_utils::XContext;
+
+// Hooks, emitted by `herbstclient --idle`, that we are interested in. Their
+// arguments are not used, because the state is queried from herbstluftwm
+// for our monitor anyway
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HerbstEvent {
+    FocusChanged,
+    TagChanged,
+    Fullscreen,
+}
+
+pub struct HerbstluftwmConnection {
+    x_context: Arc<XContext>,
+}
+
+impl HerbstluftwmConnection {
+    pub fn new(x_context: Arc<XContext>) -> Self {
+        Self { x_context }
+    }
+
+    // Returns index of the herbstluftwm monitor. Monitors in herbstluftwm are
+    // not named by default, so if there is no monitor with the given name, we
+    // look for the one, which has the same geometry as the RandR output
+    fn get_monitor_index(&self, monitor_name: &str) -> Option<u32> {
+        let count: u32 = herbstclient_attr("monitors.count")?.parse().ok()?;
+        let geometry = self
+            .x_context
+            .get_monitor_geometry(monitor_name)
+            .ok()
+            .map(|(x, y, w, h)| format!("{w}x{h}{x:+}{y:+}"));
+
+        let by_name = (0..count).find(|i| {
+            herbstclient_attr(&format!("monitors.{i}.name")).as_deref()
+                == Some(monitor_name)
+        });
+
+        by_name.or_else(|| {
+            (0..count).find(|i| {
+                herbstclient_attr(&format!("monitors.{i}.geometry")) == geometry
+            })
+        })
+    }
+}
+
+impl WmConnection for HerbstluftwmConnection {
+    fn get_focused_desktop_id(&mut self, monitor_name: &str) -> Option<u32> {
+        let monitor = self.get_monitor_index(monitor_name)?;
+        let tag = herbstclient_attr(&format!("monitors.{monitor}.tag"))?;
+
+        herbstclient_attr(&format!("tags.by-name.{tag}.index"))?
+            .parse()
+            .ok()
+    }
+
+    fn is_desk_empty(&mut self, desktop_id: u32) -> bool {
+        let client_count =
+            herbstclient_attr(&format!("tags.{desktop_id}.client_count"));
+
+        client_count.as_deref() == Some("0")
+    }
+
+    fn get_focused_window_id(&mut self, monitor_name: &str) -> Option<u32> {
+        let desktop_id = self.get_focused_desktop_id(monitor_name)?;
+
+        // There is no focused client, if tag is empty
+        let window_id = herbstclient_attr(&format!(
+            "tags.{desktop_id}.focused_client.winid"
+        ))?;
+
+        parse_window_id(&window_id)
+    }
+
+    fn get_fullscreen_window_id(
+        &mut self,
+        _x_context: Option<&XContext>,
+        desktop_id: u32,
+    ) -> Option<u32> {
+        let tag = herbstclient_attr(&format!("tags.{desktop_id}.name"))?;
+
+        // Prints ids of all fullscreen clients on the tag
+        let output = herbstclient(&[
+            "foreach",
+            "--unique",
+            "CLIENT",
+            "clients.",
+            "and",
+            ",",
+            "compare",
+            "CLIENT.tag",
+            "=",
+            &tag,
+            ",",
+            "compare",
+            "CLIENT.fullscreen",
+            "=",
+            "true",
+            ",",
+            "get_attr",
+            "CLIENT.winid",
+        ])?;
+
+        output.lines().find_map(parse_window_id)
+    }
+
+    fn get_desktops_number(&mut self, _monitor_name: &str) -> u32 {
+        herbstclient_attr("tags.count")
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(0)
+    }
+
+    // Windows are taken from the layout of the tag, for example:
+    // (split horizontal:0.5:0 (clients max:0 0x1a00003) (clients ...))
+    fn get_desktop_windows(
+        &mut self,
+        _x_context: Option<&XContext>,
+        desktop_id: u32,
+    ) -> Vec<u32> {
+        let Some(tag) = herbstclient_attr(&format!("tags.{desktop_id}.name"))
+        else {
+            return Vec::new();
+        };
+
+        let Some(layout) = herbstclient(&["layout", &tag]) else {
+            return Vec::new();
+        };
+
+        parse_layout_windows(&layout)
+    }
+}
+
+// Runs herbstclient with the given arguments and returns its output, if it
+// succeeded
+fn herbstclient(args: &[&str]) -> Option<String> {
+    let output = Command::new("herbstclient").args(args).output().ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn herbstclient_attr(path: &str) -> Option<String> {
+    herbstclient(&["get_attr", path])
+}
+
+// Window ids are printed by herbstluftwm in hex, for example 0x1a00003
+fn parse_window_id(window_id: &str) -> Option<u32> {
+    let window_id = window_id.trim().trim_start_matches("0x");
+
+    match u32::from_str_radix(window_id, 16) {
+        Ok(0) | Err(_) => None,
+        Ok(id) => Some(id),
+    }
+}
+
+fn parse_layout_windows(layout: &str) -> Vec<u32> {
+    layout
+        .split(|x: char| x.is_whitespace() || x == '(' || x == ')')
+        .filter(|x| x.starts_with("0x"))
+        .filter_map(parse_window_id)
+        .collect()
+}
+
+fn parse_hook(line: &str) -> Option<HerbstEvent> {
+    let hook = line.split('\t').next()?;
+
+    match hook {
+        "focus_changed" => Some(HerbstEvent::FocusChanged),
+        "tag_changed" => Some(HerbstEvent::TagChanged),
+        "fullscreen" => Some(HerbstEvent::Fullscreen),
+        _ => None,
+    }
+}
+
+pub fn exec(
+    x_context: XContext,
+    target: &Target,
+    config_file: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut cores = WmCore::init_all(Some(x_context), target, config_file)?;
+
+    for core in &mut cores {
+        core.process_start();
+    }
+
+    let mut idle = Command::new("herbstclient")
+        .arg("--idle")
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Couldn't subscribe to events")?;
+
+    let (sender, receiver) = mpsc::channel();
+    cores[0].listen_x11_events(sender.clone());
+
+    thread::spawn(move || {
+        let stdout = idle.stdout.take().unwrap();
+
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("While listening to events, encounter the following error: {e}");
+                    break;
+                }
+            };
+
+            if let Some(event) = parse_hook(&line) {
+                if sender.send(CoreEvent::Wm(event)).is_err() {
+                    break;
+                }
+            }
+        }
+
+        idle.kill().ok();
+        idle.wait().ok();
+    });
+
+    WmCore::run(cores, receiver, target, config_file, |core, event| {
+        if let CoreEvent::Wm(event) = event {
+            core.handle_event(*event);
+        }
+    });
+
+    Ok(())
+}
+
+impl WmCore<HerbstluftwmConnection, HerbstluftwmConfig> {
+    fn handle_event(&mut self, event: HerbstEvent) {
+        match event {
+            HerbstEvent::FocusChanged | HerbstEvent::Fullscreen => {
+                self.handle_general_event();
+            }
+
+            HerbstEvent::TagChanged => {
+                self.handle_general_event();
+
+                let Some(current_desktop) = self.get_focused_desktop_id()
+                else {
+                    return;
+                };
+
+                if self.get_fullscreen_window_id(current_desktop).is_some() {
+                    self.process_fullscreen_window();
+                }
+            }
+        }
+    }
+
+    // Focus can change on other monitor, so we always check the focused
+    // window of the tag, that is shown on our monitor
+    fn handle_general_event(&mut self) {
+        match self.get_focused_window_id() {
+            Some(id) => self.process_focused_window(id),
+            None => self.process_empty_desktop(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hook_works() {
+        assert_eq!(
+            parse_hook("focus_changed\t0x1a00003\tAlacritty"),
+            Some(HerbstEvent::FocusChanged)
+        );
+        assert_eq!(
+            parse_hook("tag_changed\tweb\t1"),
+            Some(HerbstEvent::TagChanged)
+        );
+        assert_eq!(
+            parse_hook("fullscreen\ton\t0x2c00007"),
+            Some(HerbstEvent::Fullscreen)
+        );
+        assert_eq!(parse_hook("window_title_changed\t0x1a00003\tfoo"), None);
+    }
+
+    #[test]
+    fn parse_window_id_works() {
+        assert_eq!(parse_window_id("0x1a00003"), Some(0x1a00003));
+        assert_eq!(parse_window_id("0x0"), None);
+        assert_eq!(parse_window_id("foo"), None);
+    }
+
+    #[test]
+    fn parse_layout_windows_works() {
+        let layout = "(split horizontal:0.5:0 (clients max:0 0x1a00003 \
+                      0x2c00007) (clients vertical:0))";
+
+        assert_eq!(parse_layout_windows(layout), [0x1a00003, 0x2c00007]);
+        assert!(parse_layout_windows("(clients vertical:0)").is_empty());
+    }
+}
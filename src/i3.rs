@@ -1,3 +1,4 @@
+use anyhow::Context as _;
 use i3ipc::event::{
     inner::{WindowChange, WorkspaceChange},
     Event, WindowEventInfo, WorkspaceEventInfo,
@@ -6,50 +7,78 @@ use i3ipc::event::{
 use i3ipc::{self, I3Connection, I3EventListener, Subscription};
 
 use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
 
 use crate::config::I3Config;
-use crate::core::{WmCore, WmCoreFeatures as _};
-
-pub fn exec(monitor_name: Option<&str>, config: Option<&Path>) {
-    let mut listener =
-        I3EventListener::connect().expect("Couldn't connect to event listener");
-    let mut core = WmCore::init(monitor_name, config);
-    core.process_start();
+use crate::core::{CoreEvent, Target, WmCore};
+use crate::x11_utils::XContext;
+
+pub fn exec(
+    x_context: XContext,
+    target: &Target,
+    config: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut listener = I3EventListener::connect()
+        .context("Couldn't connect to event listener")?;
+    let mut cores = WmCore::init_all(Some(x_context), target, config)?;
+
+    for core in &mut cores {
+        core.process_start();
+    }
 
     let subscriptions = [
         Subscription::Workspace,
         Subscription::Mode,
         Subscription::Window,
+        Subscription::Output,
     ];
 
     listener
         .subscribe(&subscriptions)
-        .expect("Couldn't subscribe to events");
+        .context("Couldn't subscribe to events")?;
 
-    for raw_event in listener.listen() {
-        match raw_event {
-            Ok(event) => {
-                core.handle_event(event);
-            }
+    let (sender, receiver) = mpsc::channel();
+    cores[0].listen_x11_events(sender.clone());
+
+    thread::spawn(move || {
+        for raw_event in listener.listen() {
+            match raw_event {
+                Ok(event) => {
+                    if sender.send(CoreEvent::Wm(event)).is_err() {
+                        break;
+                    }
+                }
 
-            Err(e) => {
-                eprintln!("While listening to events, encounter the following error: {e}");
+                Err(e) => {
+                    eprintln!("While listening to events, encounter the following error: {e}");
+                }
             }
         }
-    }
+    });
+
+    WmCore::run(cores, receiver, target, config, |core, event| {
+        if let CoreEvent::Wm(event) = event {
+            core.handle_event(event);
+        }
+    });
+
+    Ok(())
 }
 
 impl WmCore<I3Connection, I3Config> {
-    fn handle_event(&mut self, event: Event) {
+    fn handle_event(&mut self, event: &Event) {
         match event {
             Event::WindowEvent(e) => self.handle_window_event(e),
             Event::WorkspaceEvent(e) => self.handle_workspace_event(e),
 
-            // Prevent panic when switching binding mode or hotplugging outputs
-            Event::ModeEvent(_) | Event::OutputEvent(_) => {
+            // Prevent panic when switching binding mode
+            Event::ModeEvent(_) => {
                 self.handle_general_event();
             }
 
+            Event::OutputEvent(_) => self.process_monitor_change(),
+
             err => unreachable!("{:?}", err),
         }
     }
@@ -63,8 +92,8 @@ impl WmCore<I3Connection, I3Config> {
         };
     }
 
-    fn handle_window_event(&mut self, event_info: WindowEventInfo) {
-        let node = event_info.container;
+    fn handle_window_event(&mut self, event_info: &WindowEventInfo) {
+        let node = &event_info.container;
         let id = match node.window {
             Some(x) => x as u32,
 
@@ -82,7 +111,8 @@ impl WmCore<I3Connection, I3Config> {
         };
 
         match event_info.change {
-            WindowChange::Focus => {
+            // Window can be focused on the other monitor
+            WindowChange::Focus if self.get_focused_window_id() == Some(id) => {
                 self.process_focused_window(id);
             }
 
@@ -96,7 +126,9 @@ impl WmCore<I3Connection, I3Config> {
                 }
             }
 
-            WindowChange::FullscreenMode => {
+            WindowChange::FullscreenMode
+                if self.get_focused_window_id() == Some(id) =>
+            {
                 self.process_focused_window(id);
             }
 
@@ -104,7 +136,7 @@ impl WmCore<I3Connection, I3Config> {
         }
     }
 
-    fn handle_workspace_event(&mut self, event_info: WorkspaceEventInfo) {
+    fn handle_workspace_event(&mut self, event_info: &WorkspaceEventInfo) {
         match event_info.change {
             WorkspaceChange::Focus => {
                 let current_desktop = match self.get_focused_desktop_id() {
@@ -126,7 +158,11 @@ impl WmCore<I3Connection, I3Config> {
             }
 
             // TODO: test if this is needed
-            WorkspaceChange::Init => {
+            // New workspace is focused, so it's on our monitor only if our
+            // monitor has the focus
+            WorkspaceChange::Init
+                if self.get_focused_desktop_id().is_some() =>
+            {
                 self.process_empty_desktop();
             }
 
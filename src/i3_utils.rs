@@ -20,6 +20,33 @@ pub fn get_desktop_windows(
     vec![]
 }
 
+// Returns all windows of the desktop, both tiling and floating ones, in the
+// order of the tree
+pub fn get_desktop_window_ids(
+    conn: &mut I3Connection,
+    desktop_id: i32,
+) -> Vec<u32> {
+    let desktops = get_all_desktops(conn);
+    let desktop = desktops
+        .into_iter()
+        .find(|x| x.name == Some(desktop_id.to_string()));
+
+    match desktop {
+        Some(desktop) => get_window_ids(&desktop),
+        None => vec![],
+    }
+}
+
+fn get_window_ids(node: &Node) -> Vec<u32> {
+    let own_id = node.window.map(|x| x as u32);
+    let children = node.nodes.iter().chain(&node.floating_nodes);
+
+    own_id
+        .into_iter()
+        .chain(children.flat_map(get_window_ids))
+        .collect()
+}
+
 pub fn convert_desk_id_to_node(
     conn: &mut I3Connection,
     desktop_id: i32,
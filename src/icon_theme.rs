@@ -0,0 +1,645 @@
+// Lookup of application icons through .desktop files and icon themes, as
+// described in freedesktop specifications:
+// https://specifications.freedesktop.org/desktop-entry-spec/latest/
+// https://specifications.freedesktop.org/icon-theme-spec/latest/
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const ICON_EXTENSIONS: [&str; 2] = ["png", "svg"];
+const FALLBACK_THEME: &str = "hicolor";
+
+#[derive(Debug, Default, PartialEq)]
+struct DesktopEntry {
+    startup_wm_class: Option<String>,
+    exec: Option<String>,
+    icon: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DirectoryType {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ThemeDirectory {
+    path: String,
+    size: u32,
+    scale: u32,
+    dir_type: DirectoryType,
+    min_size: u32,
+    max_size: u32,
+    threshold: u32,
+}
+
+impl ThemeDirectory {
+    fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            size: 0,
+            scale: 1,
+            dir_type: DirectoryType::Threshold,
+            min_size: 0,
+            max_size: 0,
+            threshold: 2,
+        }
+    }
+
+    fn matches_size(&self, size: u32, scale: u32) -> bool {
+        if self.scale != scale {
+            return false;
+        }
+
+        match self.dir_type {
+            DirectoryType::Fixed => self.size == size,
+            DirectoryType::Scalable => {
+                self.min_size <= size && size <= self.max_size
+            }
+            DirectoryType::Threshold => {
+                self.size.saturating_sub(self.threshold) <= size
+                    && size <= self.size + self.threshold
+            }
+        }
+    }
+
+    fn size_distance(&self, size: u32, scale: u32) -> u32 {
+        let scaled_size = size * scale;
+
+        let (min, max) = match self.dir_type {
+            DirectoryType::Fixed => (self.size, self.size),
+            DirectoryType::Scalable => (self.min_size, self.max_size),
+            DirectoryType::Threshold => (
+                self.size.saturating_sub(self.threshold),
+                self.size + self.threshold,
+            ),
+        };
+
+        // At most one of these is non-zero
+        (min * self.scale).saturating_sub(scaled_size)
+            + scaled_size.saturating_sub(max * self.scale)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct IndexTheme {
+    directories: Vec<ThemeDirectory>,
+    inherits: Vec<String>,
+}
+
+// Returns path to the icon of the application with given WM_CLASS, which is
+// found through its .desktop file and the current icon theme
+pub fn find_app_icon(
+    wm_instance: &str,
+    wm_class: &str,
+    size: u16,
+) -> Option<PathBuf> {
+    let icon = find_desktop_entry_icon(wm_instance, wm_class)?;
+    let icon_path = Path::new(&icon);
+
+    if icon_path.is_absolute() {
+        return icon_path.is_file().then(|| icon_path.to_path_buf());
+    }
+
+    // Icon should be specified without extension, but some .desktop files
+    // still have it
+    let icon_name = match icon_path.extension() {
+        Some(ext) if ICON_EXTENSIONS.contains(&&*ext.to_string_lossy()) => {
+            icon_path.file_stem()?.to_string_lossy().to_string()
+        }
+        _ => icon,
+    };
+
+    find_icon(&icon_name, size as u32, 1)
+}
+
+// Looks up icon by its name in the current icon theme (and the themes it
+// inherits), then in the fallback theme and then in the unthemed icons
+pub fn find_icon(icon_name: &str, size: u32, scale: u32) -> Option<PathBuf> {
+    let base_dirs = icon_base_dirs();
+    let mut visited = HashSet::new();
+
+    if let Some(theme) = current_theme_name() {
+        let icon = find_icon_helper(
+            icon_name,
+            size,
+            scale,
+            &theme,
+            &base_dirs,
+            &mut visited,
+        );
+
+        if icon.is_some() {
+            return icon;
+        }
+    }
+
+    find_icon_helper(
+        icon_name,
+        size,
+        scale,
+        FALLBACK_THEME,
+        &base_dirs,
+        &mut visited,
+    )
+    .or_else(|| lookup_fallback_icon(icon_name, &base_dirs))
+}
+
+fn find_icon_helper(
+    icon_name: &str,
+    size: u32,
+    scale: u32,
+    theme: &str,
+    base_dirs: &[PathBuf],
+    visited: &mut HashSet<String>,
+) -> Option<PathBuf> {
+    // Protect against themes, that inherit each other
+    if !visited.insert(theme.to_string()) {
+        return None;
+    }
+
+    let index_theme = read_index_theme(theme, base_dirs)?;
+    let icon =
+        lookup_icon(icon_name, size, scale, theme, &index_theme, base_dirs);
+
+    if icon.is_some() {
+        return icon;
+    }
+
+    for parent in &index_theme.inherits {
+        let icon = find_icon_helper(
+            icon_name, size, scale, parent, base_dirs, visited,
+        );
+
+        if icon.is_some() {
+            return icon;
+        }
+    }
+
+    None
+}
+
+fn lookup_icon(
+    icon_name: &str,
+    size: u32,
+    scale: u32,
+    theme: &str,
+    index_theme: &IndexTheme,
+    base_dirs: &[PathBuf],
+) -> Option<PathBuf> {
+    let mut closest = None;
+    let mut min_distance = u32::MAX;
+
+    for directory in &index_theme.directories {
+        for base_dir in base_dirs {
+            for ext in ICON_EXTENSIONS {
+                let path = base_dir
+                    .join(theme)
+                    .join(&directory.path)
+                    .join(format!("{icon_name}.{ext}"));
+
+                if !path.is_file() {
+                    continue;
+                }
+
+                if directory.matches_size(size, scale) {
+                    return Some(path);
+                }
+
+                let distance = directory.size_distance(size, scale);
+
+                if distance < min_distance {
+                    min_distance = distance;
+                    closest = Some(path);
+                }
+            }
+        }
+    }
+
+    closest
+}
+
+fn lookup_fallback_icon(
+    icon_name: &str,
+    base_dirs: &[PathBuf],
+) -> Option<PathBuf> {
+    for base_dir in base_dirs {
+        for ext in ICON_EXTENSIONS {
+            let path = base_dir.join(format!("{icon_name}.{ext}"));
+
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+    }
+
+    None
+}
+
+fn read_index_theme(theme: &str, base_dirs: &[PathBuf]) -> Option<IndexTheme> {
+    for base_dir in base_dirs {
+        let index_path = base_dir.join(theme).join("index.theme");
+
+        if let Ok(content) = fs::read_to_string(index_path) {
+            return Some(parse_index_theme(&content));
+        }
+    }
+
+    None
+}
+
+fn parse_index_theme(content: &str) -> IndexTheme {
+    let mut directory_names = Vec::new();
+    let mut inherits = Vec::new();
+    let mut directories: Vec<ThemeDirectory> = Vec::new();
+    let mut section = String::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+
+        if let Some(name) =
+            line.strip_prefix('[').and_then(|x| x.strip_suffix(']'))
+        {
+            section = name.to_string();
+
+            if section != "Icon Theme" {
+                directories.push(ThemeDirectory::new(&section));
+            }
+
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+
+        if section == "Icon Theme" {
+            match key {
+                "Directories" | "ScaledDirectories" => {
+                    directory_names.extend(split_list(value));
+                }
+                "Inherits" => inherits = split_list(value),
+                _ => {}
+            }
+
+            continue;
+        }
+
+        let Some(directory) = directories.last_mut() else {
+            continue;
+        };
+
+        match key {
+            "Size" => directory.size = value.parse().unwrap_or(0),
+            "Scale" => directory.scale = value.parse().unwrap_or(1),
+            "MinSize" => directory.min_size = value.parse().unwrap_or(0),
+            "MaxSize" => directory.max_size = value.parse().unwrap_or(0),
+            "Threshold" => directory.threshold = value.parse().unwrap_or(2),
+            "Type" => {
+                directory.dir_type = match value {
+                    "Fixed" => DirectoryType::Fixed,
+                    "Scalable" => DirectoryType::Scalable,
+                    _ => DirectoryType::Threshold,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // MinSize and MaxSize default to Size, if they are not specified
+    for directory in directories.iter_mut() {
+        if directory.min_size == 0 {
+            directory.min_size = directory.size;
+        }
+
+        if directory.max_size == 0 {
+            directory.max_size = directory.size;
+        }
+    }
+
+    directories.retain(|x| directory_names.contains(&x.path));
+
+    IndexTheme {
+        directories,
+        inherits,
+    }
+}
+
+fn find_desktop_entry_icon(
+    wm_instance: &str,
+    wm_class: &str,
+) -> Option<String> {
+    let names = [wm_instance.to_lowercase(), wm_class.to_lowercase()];
+    let matches = |x: &str| names.contains(&x.to_lowercase());
+
+    let mut entries = Vec::new();
+    let mut ids = HashSet::new();
+
+    for data_dir in data_dirs() {
+        let applications_dir = data_dir.join("applications");
+        let mut files = Vec::new();
+        collect_desktop_files(&applications_dir, &mut files);
+
+        for file in files {
+            // Desktop file with the same id in the directory with higher
+            // priority hides the other ones
+            let Ok(relative) = file.strip_prefix(&applications_dir) else {
+                continue;
+            };
+            let id = relative.to_string_lossy().replace('/', "-");
+
+            if !ids.insert(id.clone()) {
+                continue;
+            }
+
+            if let Ok(content) = fs::read_to_string(&file) {
+                entries.push((id, parse_desktop_entry(&content)));
+            }
+        }
+    }
+
+    // StartupWMClass is the most reliable way to match window with its
+    // .desktop file, so it's checked first among all entries
+    let by_wm_class = entries.iter().find(|(_, entry)| {
+        entry.startup_wm_class.as_deref().is_some_and(matches)
+    });
+
+    let by_id = || {
+        entries.iter().find(|(id, _)| {
+            let id = id.trim_end_matches(".desktop");
+
+            // Reverse DNS names, like org.gnome.Nautilus
+            matches(id) || id.rsplit('.').next().is_some_and(matches)
+        })
+    };
+
+    let by_exec = || {
+        entries.iter().find(|(_, entry)| {
+            entry
+                .exec
+                .as_deref()
+                .and_then(exec_program_name)
+                .is_some_and(|x| matches(&x))
+        })
+    };
+
+    by_wm_class
+        .or_else(by_id)
+        .or_else(by_exec)
+        .and_then(|(_, entry)| entry.icon.clone())
+}
+
+fn collect_desktop_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut paths: Vec<_> = read_dir.flatten().map(|x| x.path()).collect();
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            collect_desktop_files(&path, files);
+        } else if path.extension().is_some_and(|x| x == "desktop") {
+            files.push(path);
+        }
+    }
+}
+
+fn parse_desktop_entry(content: &str) -> DesktopEntry {
+    let mut entry = DesktopEntry::default();
+    let mut in_main_section = false;
+
+    for line in content.lines() {
+        let line = line.trim();
+
+        if line.starts_with('[') {
+            in_main_section = line == "[Desktop Entry]";
+            continue;
+        }
+
+        if !in_main_section {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            let value = Some(value.trim().to_string());
+
+            match key.trim() {
+                "StartupWMClass" => entry.startup_wm_class = value,
+                "Exec" => entry.exec = value,
+                "Icon" => entry.icon = value,
+                _ => {}
+            }
+        }
+    }
+
+    entry
+}
+
+// Extracts the name of the executed program from Exec key, i.e. converts
+// `env FOO=bar /usr/bin/foo --bar %U` to `foo`
+fn exec_program_name(exec: &str) -> Option<String> {
+    let program = exec
+        .split_whitespace()
+        .map(|x| x.trim_matches('"'))
+        .find(|x| *x != "env" && !x.contains('='))?;
+
+    Path::new(program)
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var("HOME").ok().map(PathBuf::from)
+}
+
+fn data_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    match env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => dirs.push(PathBuf::from(dir)),
+        _ => {
+            if let Some(home) = home_dir() {
+                dirs.push(home.join(".local/share"));
+            }
+        }
+    }
+
+    let data_dirs = match env::var("XDG_DATA_DIRS") {
+        Ok(dirs) if !dirs.is_empty() => dirs,
+        _ => "/usr/local/share:/usr/share".to_string(),
+    };
+
+    dirs.extend(
+        data_dirs
+            .split(':')
+            .filter(|x| !x.is_empty())
+            .map(PathBuf::from),
+    );
+
+    dirs
+}
+
+fn icon_base_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if let Some(home) = home_dir() {
+        dirs.push(home.join(".icons"));
+    }
+
+    dirs.extend(data_dirs().into_iter().map(|x| x.join("icons")));
+    dirs.push(PathBuf::from("/usr/share/pixmaps"));
+
+    dirs
+}
+
+fn config_home() -> Option<PathBuf> {
+    match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => home_dir().map(|x| x.join(".config")),
+    }
+}
+
+// Reads the name of the icon theme, selected by user in GTK or KDE settings
+fn current_theme_name() -> Option<String> {
+    let config_home = config_home()?;
+    let settings = [
+        ("gtk-3.0/settings.ini", "Settings", "gtk-icon-theme-name"),
+        ("gtk-4.0/settings.ini", "Settings", "gtk-icon-theme-name"),
+        ("kdeglobals", "Icons", "Theme"),
+    ];
+
+    settings.iter().find_map(|(file, section, key)| {
+        let content = fs::read_to_string(config_home.join(file)).ok()?;
+        read_ini_value(&content, section, key)
+    })
+}
+
+fn read_ini_value(content: &str, section: &str, key: &str) -> Option<String> {
+    let mut in_section = false;
+
+    for line in content.lines() {
+        let line = line.trim();
+
+        if line.starts_with('[') {
+            in_section = line == format!("[{section}]");
+            continue;
+        }
+
+        if !in_section {
+            continue;
+        }
+
+        if let Some((k, v)) = line.split_once('=') {
+            if k.trim() == key {
+                return Some(v.trim().trim_matches('"').to_string());
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_desktop_entry_works() {
+        let content = "\
+[Desktop Entry]
+Name=Foo
+Icon=foo-icon
+Icon[de]=foo-icon-de
+Exec=env GDK_BACKEND=x11 /usr/bin/foo %U
+StartupWMClass=FooApp
+
+[Desktop Action new-window]
+Exec=/usr/bin/foo --new-window
+Icon=bar-icon
+";
+        let entry = parse_desktop_entry(content);
+
+        assert_eq!(entry.icon.as_deref(), Some("foo-icon"));
+        assert_eq!(entry.startup_wm_class.as_deref(), Some("FooApp"));
+        assert_eq!(
+            entry.exec.as_deref().and_then(exec_program_name).as_deref(),
+            Some("foo")
+        );
+    }
+
+    #[test]
+    fn parse_index_theme_works() {
+        let content = "\
+[Icon Theme]
+Name=Foo
+Inherits=Adwaita, hicolor
+Directories=16x16/apps,scalable/apps
+
+[16x16/apps]
+Size=16
+Type=Fixed
+
+[scalable/apps]
+Size=48
+MinSize=8
+MaxSize=512
+Type=Scalable
+
+[unlisted]
+Size=32
+";
+        let theme = parse_index_theme(content);
+
+        assert_eq!(theme.inherits, vec!["Adwaita", "hicolor"]);
+        assert_eq!(theme.directories.len(), 2);
+
+        let fixed = &theme.directories[0];
+        assert!(fixed.matches_size(16, 1));
+        assert!(!fixed.matches_size(24, 1));
+        assert_eq!(fixed.size_distance(24, 1), 8);
+
+        let scalable = &theme.directories[1];
+        assert!(scalable.matches_size(24, 1));
+        assert!(!scalable.matches_size(24, 2));
+        assert_eq!(scalable.size_distance(1024, 1), 512);
+    }
+
+    #[test]
+    fn threshold_directory_works() {
+        let mut directory = ThemeDirectory::new("22x22/apps");
+        directory.size = 22;
+
+        assert!(directory.matches_size(24, 1));
+        assert!(!directory.matches_size(32, 1));
+        assert_eq!(directory.size_distance(32, 1), 8);
+    }
+
+    #[test]
+    fn read_ini_value_works() {
+        let content = "\
+[Settings]
+gtk-theme-name=Adwaita
+gtk-icon-theme-name = \"Papirus\"
+";
+
+        assert_eq!(
+            read_ini_value(content, "Settings", "gtk-icon-theme-name"),
+            Some("Papirus".to_string())
+        );
+        assert_eq!(read_ini_value(content, "Icons", "Theme"), None);
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::Context as _;
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use x11rb::protocol::Event;
+
+use crate::config::LeftwmConfig;
+use crate::core::{CoreEvent, Target, WmCore};
+use crate::ewmh::EwmhConnection;
+use crate::wm_connection::WmConnection;
+use crate::x11_utils::XContext;
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Viewport {
+    tag: String,
+    x: i32,
+    y: i32,
+    w: u32,
+    h: u32,
+}
+
+// Snapshot of the state, printed by `leftwm-state` on every change
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct LeftwmState {
+    desktop_names: Vec<String>,
+    viewports: Vec<Viewport>,
+
+    // Tags, which have at least one window
+    working_tags: Vec<String>,
+}
+
+impl LeftwmState {
+    // Returns index of the tag, shown on the viewport with the given
+    // geometry. This index is also used by leftwm for _NET_WM_DESKTOP
+    fn get_viewport_desktop(
+        &self,
+        geometry: (i16, i16, u16, u16),
+    ) -> Option<u32> {
+        let (x, y, w, h) = geometry;
+        let viewport = self.viewports.iter().find(|v| {
+            (v.x, v.y, v.w, v.h) == (x as i32, y as i32, w as u32, h as u32)
+        });
+
+        // Viewports can be smaller, than monitors, if they are specified in
+        // the config of leftwm, so we fall back to the position only
+        let viewport = viewport.or_else(|| {
+            self.viewports
+                .iter()
+                .find(|v| (v.x, v.y) == (x as i32, y as i32))
+        })?;
+
+        self.desktop_names
+            .iter()
+            .position(|x| *x == viewport.tag)
+            .map(|x| x as u32)
+    }
+
+    fn is_desk_empty(&self, desktop_id: u32) -> bool {
+        match self.desktop_names.get(desktop_id as usize) {
+            Some(tag) => !self.working_tags.contains(tag),
+            None => true,
+        }
+    }
+}
+
+fn parse_state(line: &str) -> Option<LeftwmState> {
+    serde_json::from_str(line).ok()
+}
+
+// Tags and viewports are taken from the state of leftwm, while windows are
+// looked up through EWMH, because the state doesn't contain them
+pub struct LeftwmConnection {
+    ewmh: EwmhConnection,
+    x_context: Arc<XContext>,
+    state: LeftwmState,
+
+    // Focused window of each desktop, which is remembered, because
+    // _NET_ACTIVE_WINDOW can belong to the other monitor
+    focused_windows: HashMap<u32, u32>,
+}
+
+impl LeftwmConnection {
+    pub fn new(x_context: Arc<XContext>) -> anyhow::Result<Self> {
+        let ewmh = EwmhConnection::new(Arc::clone(&x_context))?;
+
+        // Prints the state once and quits
+        let output = Command::new("leftwm-state")
+            .arg("--quit")
+            .output()
+            .context("Couldn't get state of leftwm")?;
+        let state = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(parse_state)
+            .unwrap_or_default();
+
+        Ok(Self {
+            ewmh,
+            x_context,
+            state,
+            focused_windows: HashMap::new(),
+        })
+    }
+
+    // Returns true, if the state has changed
+    pub fn update_state(&mut self, state: LeftwmState) -> bool {
+        if self.state == state {
+            return false;
+        }
+
+        self.state = state;
+        true
+    }
+}
+
+impl WmConnection for LeftwmConnection {
+    fn get_focused_desktop_id(&mut self, monitor_name: &str) -> Option<u32> {
+        let geometry =
+            self.x_context.get_monitor_geometry(monitor_name).ok()?;
+
+        self.state.get_viewport_desktop(geometry)
+    }
+
+    fn is_desk_empty(&mut self, desktop_id: u32) -> bool {
+        self.state.is_desk_empty(desktop_id)
+    }
+
+    fn get_focused_window_id(&mut self, monitor_name: &str) -> Option<u32> {
+        let desktop_id = self.get_focused_desktop_id(monitor_name)?;
+
+        if let Some(window) = self.ewmh.get_active_window() {
+            if self.ewmh.get_window_desktop(window) == Some(desktop_id) {
+                self.focused_windows.insert(desktop_id, window);
+                return Some(window);
+            }
+        }
+
+        // Focus is on the other monitor, so we keep showing the window, that
+        // was focused here last, if it's still there
+        let window = *self.focused_windows.get(&desktop_id)?;
+
+        self.ewmh
+            .get_visible_windows(desktop_id)
+            .contains(&window)
+            .then_some(window)
+    }
+
+    fn get_fullscreen_window_id(
+        &mut self,
+        x_context: Option<&XContext>,
+        desktop_id: u32,
+    ) -> Option<u32> {
+        self.ewmh.get_fullscreen_window_id(x_context, desktop_id)
+    }
+
+    fn get_desktops_number(&mut self, _monitor_name: &str) -> u32 {
+        self.state.desktop_names.len() as u32
+    }
+
+    fn get_desktop_windows(
+        &mut self,
+        _x_context: Option<&XContext>,
+        desktop_id: u32,
+    ) -> Vec<u32> {
+        self.ewmh.get_visible_windows(desktop_id)
+    }
+}
+
+pub fn exec(
+    x_context: XContext,
+    target: &Target,
+    config_file: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut cores: Vec<WmCore<LeftwmConnection, LeftwmConfig>> =
+        WmCore::init_all(Some(x_context), target, config_file)?;
+
+    for core in &mut cores {
+        core.process_start();
+    }
+
+    let mut leftwm_state = Command::new("leftwm-state")
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Couldn't subscribe to events")?;
+
+    let (sender, receiver) = mpsc::channel();
+    cores[0].listen_x11_events(sender.clone());
+
+    thread::spawn(move || {
+        let stdout = leftwm_state.stdout.take().unwrap();
+
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("While listening to events, encounter the following error: {e}");
+                    break;
+                }
+            };
+
+            if let Some(state) = parse_state(&line) {
+                if sender.send(CoreEvent::Wm(state)).is_err() {
+                    break;
+                }
+            }
+        }
+
+        leftwm_state.kill().ok();
+        leftwm_state.wait().ok();
+    });
+
+    WmCore::run(
+        cores,
+        receiver,
+        target,
+        config_file,
+        |core, event| match event {
+            CoreEvent::Wm(state) => {
+                if core.wm_connection().update_state(state.clone()) {
+                    core.handle_general_event();
+                }
+            }
+
+            CoreEvent::X11(event) => core.handle_event(event),
+        },
+    );
+
+    Ok(())
+}
+
+impl WmCore<LeftwmConnection, LeftwmConfig> {
+    // Focus changes are not always reflected in the state of leftwm (for
+    // example, when windows have the same title), so they are taken from the
+    // root window
+    fn handle_event(&mut self, event: &Event) {
+        let Event::PropertyNotify(e) = event else {
+            return;
+        };
+
+        let Some(x_context) = self.x_context() else {
+            return;
+        };
+
+        let atoms = x_context.atoms;
+        let root = x_context.screen().root;
+
+        if e.window == root && e.atom == atoms._NET_ACTIVE_WINDOW {
+            self.handle_general_event();
+        }
+
+        // Focused window was toggled to or from fullscreen
+        if e.atom == atoms._NET_WM_STATE
+            && self.get_focused_window_id() == Some(e.window)
+        {
+            self.handle_general_event();
+        }
+    }
+
+    fn handle_general_event(&mut self) {
+        let Some(desktop) = self.get_focused_desktop_id() else {
+            return;
+        };
+
+        if self.is_desk_empty(desktop) {
+            self.process_empty_desktop();
+            return;
+        }
+
+        match self.get_focused_window_id() {
+            Some(id) => self.process_focused_window(id),
+            None => self.process_empty_desktop(),
+        }
+
+        if self.get_fullscreen_window_id(desktop).is_some() {
+            self.process_fullscreen_window();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATE: &str = r#"{"window_title":"Alacritty","desktop_names":["1","2","3"],"viewports":[{"tag":"1","h":1080,"w":1920,"x":0,"y":0,"layout":"MainAndVertStack"},{"tag":"3","h":1050,"w":1680,"x":1920,"y":0,"layout":"Monocle"}],"active_desktop":["1"],"working_tags":["1","2"],"urgent_tags":[]}"#;
+
+    #[test]
+    fn parse_state_works() {
+        let state = parse_state(STATE).unwrap();
+
+        assert_eq!(state.get_viewport_desktop((0, 0, 1920, 1080)), Some(0));
+        assert_eq!(state.get_viewport_desktop((1920, 0, 1680, 1080)), Some(2));
+        assert_eq!(state.get_viewport_desktop((0, 1080, 1920, 1080)), None);
+        assert!(!state.is_desk_empty(1));
+        assert!(state.is_desk_empty(2));
+        assert!(state.is_desk_empty(5));
+        assert_eq!(parse_state("Alacritty"), None);
+    }
+}
@@ -1,35 +1,116 @@
-use clap::Parser;
+use anyhow::Context as _;
+use clap::{Parser, Subcommand};
+use std::env;
 use std::path::PathBuf;
+use std::process;
 
+mod backend;
+#[cfg(feature = "bspwm")]
 mod bspwm;
 mod config;
 mod core;
+mod daemon;
+#[cfg(feature = "ewmh")]
+mod ewmh;
+#[cfg(feature = "herbstluftwm")]
+mod herbstluftwm;
+#[cfg(feature = "i3")]
 mod i3;
+#[cfg(feature = "i3")]
 mod i3_utils;
+mod icon_theme;
+#[cfg(feature = "leftwm")]
+mod leftwm;
+#[cfg(feature = "sway")]
+mod sway;
 mod wm_connection;
 mod x11_utils;
 
+use backend::Backend;
+use core::Target;
+use daemon::Server;
+use x11_utils::XContext;
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Opts {
     #[arg(long, short)]
     monitor_name: Option<String>,
 
-    #[arg(long = "config", short)]
+    #[arg(long = "config", short, global = true)]
     config_path: Option<PathBuf>,
+
+    /// X display to connect to instead of $DISPLAY (for example `:1.0`)
+    #[arg(long, global = true)]
+    display: Option<String>,
+
+    /// Backend to use instead of the detected one (i3, bspwm, ewmh, ...)
+    #[arg(long, global = true)]
+    wm: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serve info of all the monitors from one process
+    Daemon,
+
+    /// Print info of the monitor, received from the running daemon
+    Listen {
+        #[arg(long, short)]
+        monitor: String,
+    },
 }
 
 fn main() {
     let options = Opts::parse();
-    let config_path = options.config_path.as_deref();
-    let monitor_name = options.monitor_name.as_deref();
 
-    let wm_name = x11_utils::get_current_wm()
-        .expect("Couldn't get current window manager name");
+    if let Err(e) = run(&options) {
+        // Polybar swallows stderr, so the error is printed as the output of
+        // the module as well
+        println!("ixwindow: {e}");
+        eprintln!("Error: {e:#}");
+        process::exit(1);
+    }
+}
+
+fn run(options: &Opts) -> anyhow::Result<()> {
+    let config_path = options.config_path.as_deref();
 
-    match wm_name.as_str() {
-        "i3" => i3::exec(monitor_name, config_path),
-        "bspwm" => bspwm::exec(monitor_name, config_path),
-        _ => {}
+    // Tools, that we run, like `herbstclient`, connect to X server on their
+    // own, so they have to get the same display. It's set before any thread
+    // is spawned
+    if let Some(display) = &options.display {
+        env::set_var("DISPLAY", display);
     }
+
+    let target = match &options.command {
+        Some(Command::Listen { monitor }) => return daemon::listen(monitor),
+        Some(Command::Daemon) => Target::Daemon(Server::bind()?),
+        None => Target::Monitor(options.monitor_name.clone()),
+    };
+
+    // X server may be absent at all, for example on sway, unless the display
+    // was given explicitly
+    let x_context = match &options.display {
+        Some(display) => Some(
+            XContext::new(Some(display))
+                .with_context(|| format!("Couldn't connect to `{display}`"))?,
+        ),
+        None => XContext::new(None).ok(),
+    };
+
+    let wm_name = match &options.wm {
+        Some(x) => Some(x.clone()),
+        None => config::load_wm(config_path)?,
+    };
+
+    let backend = match wm_name {
+        Some(x) => Backend::from_name(&x)?,
+        None => Backend::detect(x_context.as_ref())?,
+    };
+
+    backend.exec(x_context, &target, config_path)
 }
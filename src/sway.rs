@@ -0,0 +1,513 @@
+use anyhow::{bail, Context as _};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::config::{SwayConfig, WindowInfoType};
+use crate::core::{CoreEvent, Target, WindowInfo, WmCore};
+use crate::wm_connection::WmConnection;
+use crate::x11_utils::XContext;
+
+// https://man.archlinux.org/man/sway-ipc.7
+const MAGIC: &[u8] = b"i3-ipc";
+const SUBSCRIBE: u32 = 2;
+const GET_WORKSPACES: u32 = 1;
+const GET_OUTPUTS: u32 = 3;
+const GET_TREE: u32 = 4;
+const EVENT_WORKSPACE: u32 = 0x80000000;
+const EVENT_WINDOW: u32 = 0x80000003;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct WindowProperties {
+    class: Option<String>,
+    instance: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Node {
+    id: i64,
+    name: Option<String>,
+
+    #[serde(rename = "type")]
+    node_type: String,
+
+    num: Option<i32>,
+    focused: bool,
+    fullscreen_mode: u8,
+
+    // Set for native Wayland windows
+    app_id: Option<String>,
+
+    // Set for Xwayland windows
+    window_properties: Option<WindowProperties>,
+
+    nodes: Vec<Node>,
+    floating_nodes: Vec<Node>,
+}
+
+impl Node {
+    fn is_window(&self) -> bool {
+        matches!(self.node_type.as_str(), "con" | "floating_con")
+            && self.nodes.is_empty()
+    }
+
+    // Iterates over this node and all its descendants
+    fn descendants(&self) -> Vec<&Node> {
+        let mut result = vec![self];
+
+        for node in self.nodes.iter().chain(&self.floating_nodes) {
+            result.extend(node.descendants());
+        }
+
+        result
+    }
+
+    fn find(&self, predicate: impl Fn(&Node) -> bool) -> Option<&Node> {
+        self.descendants().into_iter().find(|x| predicate(x))
+    }
+
+    fn app_name(&self) -> Option<&str> {
+        let properties = self.window_properties.as_ref();
+
+        self.app_id
+            .as_deref()
+            .or(properties.and_then(|x| x.class.as_deref()))
+    }
+
+    fn info(&self, info_type: WindowInfoType) -> Option<&str> {
+        let properties = self.window_properties.as_ref();
+
+        match info_type {
+            WindowInfoType::WmName
+            | WindowInfoType::NetWmName
+            | WindowInfoType::NetWmVisibleName => self.name.as_deref(),
+
+            WindowInfoType::WmClass => self.app_name(),
+
+            WindowInfoType::WmInstance => self
+                .app_id
+                .as_deref()
+                .or(properties.and_then(|x| x.instance.as_deref())),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Workspace {
+    num: i32,
+    visible: bool,
+    output: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Output {
+    name: String,
+    active: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct EventInfo {
+    change: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum SwayEvent {
+    Workspace(String),
+    Window(String),
+}
+
+// Minimal client for the IPC protocol of sway (which is the same as the one
+// of i3). It doesn't need X server to run
+pub struct SwayConnection {
+    stream: UnixStream,
+}
+
+impl SwayConnection {
+    pub fn connect() -> anyhow::Result<Self> {
+        let Some(socket_path) = env::var_os("SWAYSOCK") else {
+            bail!("$SWAYSOCK is not set");
+        };
+
+        Self::connect_to(Path::new(&socket_path))
+    }
+
+    pub fn connect_to(socket_path: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            stream: UnixStream::connect(socket_path)?,
+        })
+    }
+
+    fn send_message(
+        &self,
+        message_type: u32,
+        payload: &str,
+    ) -> anyhow::Result<()> {
+        let mut message = MAGIC.to_vec();
+        message.extend((payload.len() as u32).to_ne_bytes());
+        message.extend(message_type.to_ne_bytes());
+        message.extend(payload.as_bytes());
+
+        (&self.stream).write_all(&message)?;
+
+        Ok(())
+    }
+
+    fn read_message(&self) -> anyhow::Result<(u32, Vec<u8>)> {
+        let mut header = [0; 14];
+        (&self.stream).read_exact(&mut header)?;
+
+        if &header[..6] != MAGIC {
+            bail!("Invalid reply from sway");
+        }
+
+        let length = u32::from_ne_bytes(header[6..10].try_into()?);
+        let message_type = u32::from_ne_bytes(header[10..14].try_into()?);
+
+        let mut payload = vec![0; length as usize];
+        (&self.stream).read_exact(&mut payload)?;
+
+        Ok((message_type, payload))
+    }
+
+    fn request<T: DeserializeOwned>(
+        &self,
+        message_type: u32,
+        payload: &str,
+    ) -> anyhow::Result<T> {
+        self.send_message(message_type, payload)?;
+        let (_, reply) = self.read_message()?;
+
+        Ok(serde_json::from_slice(&reply)?)
+    }
+
+    pub fn get_tree(&self) -> anyhow::Result<Node> {
+        self.request(GET_TREE, "")
+    }
+
+    pub fn get_workspaces(&self) -> anyhow::Result<Vec<Workspace>> {
+        self.request(GET_WORKSPACES, "")
+    }
+
+    // Returns names of all the outputs, that are turned on
+    pub fn get_outputs(&self) -> anyhow::Result<Vec<String>> {
+        let outputs: Vec<Output> = self.request(GET_OUTPUTS, "")?;
+
+        Ok(outputs
+            .into_iter()
+            .filter(|x| x.active)
+            .map(|x| x.name)
+            .collect())
+    }
+
+    pub fn subscribe(&self, events: &[&str]) -> anyhow::Result<()> {
+        #[derive(Deserialize)]
+        struct Reply {
+            success: bool,
+        }
+
+        let reply: Reply =
+            self.request(SUBSCRIBE, &serde_json::to_string(events)?)?;
+
+        if !reply.success {
+            bail!("Couldn't subscribe to events");
+        }
+
+        Ok(())
+    }
+
+    // Blocks until the next event arrives. Events, we are not interested in,
+    // are returned as None
+    pub fn next_event(&self) -> anyhow::Result<Option<SwayEvent>> {
+        let (message_type, payload) = self.read_message()?;
+        let event_info = || -> anyhow::Result<String> {
+            Ok(serde_json::from_slice::<EventInfo>(&payload)?.change)
+        };
+
+        match message_type {
+            EVENT_WORKSPACE => Ok(Some(SwayEvent::Workspace(event_info()?))),
+            EVENT_WINDOW => Ok(Some(SwayEvent::Window(event_info()?))),
+            _ => Ok(None),
+        }
+    }
+
+    fn find_window(&self, window_id: u32) -> Option<Node> {
+        let tree = self.get_tree().ok()?;
+
+        tree.find(|x| x.is_window() && x.id as u32 == window_id)
+            .cloned()
+    }
+
+    fn find_workspace(&self, desktop_id: u32) -> Option<Node> {
+        let tree = self.get_tree().ok()?;
+
+        tree.find(|x| {
+            x.node_type == "workspace" && x.num == Some(desktop_id as i32)
+        })
+        .cloned()
+    }
+}
+
+// Window ids here are ids of sway containers, because Wayland windows don't
+// have X11 ids
+impl WmConnection for SwayConnection {
+    fn is_window_fullscreen(
+        &self,
+        _x_context: Option<&XContext>,
+        window_id: u32,
+    ) -> bool {
+        self.find_window(window_id)
+            .is_some_and(|x| x.fullscreen_mode != 0)
+    }
+
+    fn get_window_name(
+        &self,
+        _x_context: Option<&XContext>,
+        window_id: u32,
+    ) -> Option<String> {
+        let window = self.find_window(window_id)?;
+
+        Some(window.app_name()?.replace(' ', "-"))
+    }
+
+    fn get_window_info(
+        &self,
+        _x_context: Option<&XContext>,
+        window_id: u32,
+        info_types: &[WindowInfoType],
+    ) -> Option<WindowInfo> {
+        let window = self.find_window(window_id)?;
+
+        for &info_type in info_types {
+            if let Some(info) = window.info(info_type) {
+                if !info.is_empty() {
+                    return Some(WindowInfo {
+                        info: info.to_string(),
+                        info_type,
+                    });
+                }
+            }
+        }
+
+        Some(WindowInfo {
+            info: String::new(),
+            info_type: info_types.last()?.to_owned(),
+        })
+    }
+
+    fn get_focused_desktop_id(&mut self, monitor_name: &str) -> Option<u32> {
+        let workspaces = self.get_workspaces().ok()?;
+
+        workspaces
+            .into_iter()
+            .find(|x| x.visible && x.output == monitor_name)
+            .map(|x| x.num as u32)
+    }
+
+    fn is_desk_empty(&mut self, desktop_id: u32) -> bool {
+        match self.find_workspace(desktop_id) {
+            Some(x) => x.nodes.is_empty() && x.floating_nodes.is_empty(),
+            None => true,
+        }
+    }
+
+    fn get_focused_window_id(&mut self, monitor_name: &str) -> Option<u32> {
+        let tree = self.get_tree().ok()?;
+        let output = tree.find(|x| {
+            x.node_type == "output" && x.name.as_deref() == Some(monitor_name)
+        })?;
+
+        // Focused node can be workspace, when it's empty
+        output
+            .find(|x| x.focused && x.is_window())
+            .map(|x| x.id as u32)
+    }
+
+    fn get_fullscreen_window_id(
+        &mut self,
+        _x_context: Option<&XContext>,
+        desktop_id: u32,
+    ) -> Option<u32> {
+        let workspace = self.find_workspace(desktop_id)?;
+
+        workspace
+            .find(|x| x.is_window() && x.fullscreen_mode != 0)
+            .map(|x| x.id as u32)
+    }
+
+    fn get_desktops_number(&mut self, monitor_name: &str) -> u32 {
+        let workspaces = self.get_workspaces().unwrap_or_default();
+
+        workspaces
+            .iter()
+            .filter(|x| x.output == monitor_name)
+            .count() as u32
+    }
+}
+
+// Returns name of the focused output, which is used, when monitor is not
+// specified
+pub fn get_focused_output(connection: &SwayConnection) -> Option<String> {
+    let tree = connection.get_tree().ok()?;
+    let outputs = tree.nodes.iter().filter(|x| x.node_type == "output");
+
+    for output in outputs {
+        if output.find(|x| x.focused).is_some() {
+            return output.name.clone();
+        }
+    }
+
+    None
+}
+
+pub fn exec(target: &Target, config_file: Option<&Path>) -> anyhow::Result<()> {
+    let listener = SwayConnection::connect()
+        .context("Couldn't connect to event listener")?;
+    let mut cores = WmCore::init_all(None, target, config_file)?;
+
+    for core in &mut cores {
+        core.process_start();
+    }
+
+    listener
+        .subscribe(&["workspace", "window"])
+        .context("Couldn't subscribe to events")?;
+
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || loop {
+        match listener.next_event() {
+            Ok(Some(event)) => {
+                if sender.send(CoreEvent::Wm(event)).is_err() {
+                    break;
+                }
+            }
+
+            Ok(None) => {}
+
+            Err(e) => {
+                eprintln!("While listening to events, encounter the following error: {e}");
+                break;
+            }
+        }
+    });
+
+    for event in receiver {
+        for core in &mut cores {
+            match &event {
+                CoreEvent::Wm(event) => core.handle_event(event),
+
+                // There is no X server on Wayland, so these never come
+                #[cfg(feature = "x11")]
+                CoreEvent::X11(_) => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl WmCore<SwayConnection, SwayConfig> {
+    fn handle_event(&mut self, event: &SwayEvent) {
+        match event {
+            SwayEvent::Window(change) => match change.as_str() {
+                "focus" | "close" | "fullscreen_mode" | "move" => {
+                    self.handle_general_event();
+                }
+
+                // There are no X properties to watch on Wayland, so title is
+                // updated from the events
+                "title" => self.update_window_info(),
+
+                _ => {}
+            },
+
+            SwayEvent::Workspace(change) => match change.as_str() {
+                "focus" | "init" | "empty" => self.handle_general_event(),
+                _ => {}
+            },
+        }
+    }
+
+    fn handle_general_event(&mut self) {
+        match self.get_focused_window_id() {
+            Some(id) => self.process_focused_window(id),
+            None => self.process_empty_desktop(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    const TREE: &str = r#"{
+        "id": 1, "name": "root", "type": "root", "nodes": [{
+            "id": 3, "name": "eDP-1", "type": "output", "nodes": [{
+                "id": 4, "name": "1", "type": "workspace", "num": 1,
+                "nodes": [{
+                    "id": 5, "name": "~/src", "type": "con",
+                    "focused": true, "app_id": "foot", "nodes": []
+                }, {
+                    "id": 6, "name": "Firefox", "type": "con",
+                    "fullscreen_mode": 1, "app_id": null,
+                    "window_properties": {
+                        "class": "firefox", "instance": "Navigator"
+                    }
+                }]
+            }]
+        }]
+    }"#;
+
+    // Replies to every request with the same tree, like sway would do
+    fn spawn_sway_stand_in(socket_path: &Path, requests: usize) {
+        let listener = UnixListener::bind(socket_path).unwrap();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let connection = SwayConnection { stream };
+
+            for _ in 0..requests {
+                let (message_type, _) = connection.read_message().unwrap();
+                connection.send_message(message_type, TREE).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn sway_connection_works() {
+        let socket_path = env::temp_dir()
+            .join(format!("ixwindow-sway-{}.sock", std::process::id()));
+        spawn_sway_stand_in(&socket_path, 4);
+
+        let mut connection = SwayConnection::connect_to(&socket_path).unwrap();
+
+        assert_eq!(connection.get_focused_window_id("eDP-1"), Some(5));
+        assert!(connection.is_window_fullscreen(None, 6));
+        assert_eq!(
+            connection.get_window_name(None, 6),
+            Some("firefox".to_string())
+        );
+        assert_eq!(
+            connection.get_window_info(
+                None,
+                5,
+                &[WindowInfoType::WmInstance, WindowInfoType::NetWmName]
+            ),
+            Some(WindowInfo {
+                info: "foot".to_string(),
+                info_type: WindowInfoType::WmInstance,
+            })
+        );
+
+        std::fs::remove_file(socket_path).unwrap();
+    }
+}
@@ -1,33 +1,103 @@
-use bspc_rs::errors::ReplyError;
-use bspc_rs::query;
-use bspc_rs::selectors::{DesktopSelector, MonitorSelector, NodeSelector};
-use bspc_rs::Id;
-
+#[cfg(feature = "bspwm")]
+use bspc_rs::{
+    errors::ReplyError,
+    query,
+    selectors::{DesktopSelector, MonitorSelector, NodeSelector},
+    Id,
+};
+
+#[cfg(feature = "i3")]
 use i3ipc::I3Connection;
 
+use anyhow::bail;
+
 use std::str;
 
+use crate::config::WindowInfoType;
+use crate::core::WindowInfo;
+use crate::x11_utils::XContext;
+
+#[cfg(feature = "bspwm")]
 use crate::bspwm::BspwmConnection;
-use crate::{i3_utils, x11_utils};
+#[cfg(feature = "i3")]
+use crate::i3_utils;
 
+// Default implementations read information about windows from X server.
+// `x_context` is None, when window manager runs without it (on Wayland)
 pub trait WmConnection {
-    fn is_window_fullscreen(&self, window_id: u32) -> bool {
+    fn is_window_fullscreen(
+        &self,
+        x_context: Option<&XContext>,
+        window_id: u32,
+    ) -> bool {
+        let Some(x_context) = x_context else {
+            return false;
+        };
+
         // We can't just use unwrap here, because some apps (at least Discord
         // and Zoom) that are changing its window_id as it is running
-        x11_utils::is_window_fullscreen(window_id).unwrap_or(false)
+        x_context.is_window_fullscreen(window_id).unwrap_or(false)
+    }
+
+    fn get_window_name(
+        &self,
+        x_context: Option<&XContext>,
+        window_id: u32,
+    ) -> Option<String> {
+        Some(x_context?.get_wm_class(window_id).ok()?.replace(' ', "-"))
     }
 
-    fn get_window_name(&self, window_id: u32) -> Option<String> {
-        Some(x11_utils::get_wm_class(window_id).ok()?.replace(' ', "-"))
+    fn get_window_info(
+        &self,
+        x_context: Option<&XContext>,
+        window_id: u32,
+        info_types: &[WindowInfoType],
+    ) -> Option<WindowInfo> {
+        x_context?.get_window_info(window_id, info_types).ok()
+    }
+
+    // Window managers, which run on X server, handle _NET_ACTIVE_WINDOW.
+    // Focus is changed only by clicks on the icon, so it's not used without
+    // X server
+    #[allow(dead_code)]
+    fn focus_window(
+        &mut self,
+        x_context: Option<&XContext>,
+        window_id: u32,
+    ) -> anyhow::Result<()> {
+        let Some(x_context) = x_context else {
+            bail!("Window can't be focused without X server");
+        };
+
+        x_context.activate_window(window_id)
+    }
+
+    // Returns windows of the desktop in the order, in which focus is cycled
+    // through them
+    #[allow(dead_code)]
+    fn get_desktop_windows(
+        &mut self,
+        _x_context: Option<&XContext>,
+        _desktop_id: u32,
+    ) -> Vec<u32> {
+        Vec::new()
     }
 
     fn get_focused_desktop_id(&mut self, monitor_name: &str) -> Option<u32>;
     fn is_desk_empty(&mut self, desktop_id: u32) -> bool;
     fn get_focused_window_id(&mut self, monitor_name: &str) -> Option<u32>;
-    fn get_fullscreen_window_id(&mut self, desktop_id: u32) -> Option<u32>;
+    fn get_fullscreen_window_id(
+        &mut self,
+        x_context: Option<&XContext>,
+        desktop_id: u32,
+    ) -> Option<u32>;
+
+    // Not used yet, but may be useful for switching desktops
+    #[allow(dead_code)]
     fn get_desktops_number(&mut self, monitor_name: &str) -> u32;
 }
 
+#[cfg(feature = "i3")]
 impl WmConnection for I3Connection {
     fn get_focused_desktop_id(&mut self, monitor_name: &str) -> Option<u32> {
         let desktops = self
@@ -65,12 +135,16 @@ impl WmConnection for I3Connection {
         None
     }
 
-    fn get_fullscreen_window_id(&mut self, desktop_id: u32) -> Option<u32> {
+    fn get_fullscreen_window_id(
+        &mut self,
+        x_context: Option<&XContext>,
+        desktop_id: u32,
+    ) -> Option<u32> {
         let nodes = i3_utils::get_desktop_windows(self, desktop_id as i32);
 
         for node in nodes {
             if let Some(id) = node.window {
-                if self.is_window_fullscreen(id as u32) {
+                if self.is_window_fullscreen(x_context, id as u32) {
                     return Some(id as u32);
                 }
             }
@@ -83,8 +157,17 @@ impl WmConnection for I3Connection {
     fn get_desktops_number(&mut self, monitor_name: &str) -> u32 {
         i3_utils::get_desktops_number(self, monitor_name)
     }
+
+    fn get_desktop_windows(
+        &mut self,
+        _x_context: Option<&XContext>,
+        desktop_id: u32,
+    ) -> Vec<u32> {
+        i3_utils::get_desktop_window_ids(self, desktop_id as i32)
+    }
 }
 
+#[cfg(feature = "bspwm")]
 impl WmConnection for BspwmConnection {
     fn get_focused_desktop_id(&mut self, monitor_name: &str) -> Option<u32> {
         let query_result = query::query_desktops(
@@ -121,7 +204,11 @@ impl WmConnection for BspwmConnection {
         from_query_result_to_id(query_result)
     }
 
-    fn get_fullscreen_window_id(&mut self, desktop_id: u32) -> Option<u32> {
+    fn get_fullscreen_window_id(
+        &mut self,
+        _x_context: Option<&XContext>,
+        desktop_id: u32,
+    ) -> Option<u32> {
         let desk_id = desktop_id.to_string();
         let query_result = query::query_nodes(
             None,
@@ -149,8 +236,26 @@ impl WmConnection for BspwmConnection {
             }
         }
     }
+
+    fn get_desktop_windows(
+        &mut self,
+        _x_context: Option<&XContext>,
+        desktop_id: u32,
+    ) -> Vec<u32> {
+        let desk_id = desktop_id.to_string();
+        let query_result = query::query_nodes(
+            None,
+            None,
+            Some(DesktopSelector(&desk_id)),
+            Some(NodeSelector(".window.!hidden")),
+        );
+
+        // Empty desktop is reported as an error with empty message
+        query_result.unwrap_or_default()
+    }
 }
 
+#[cfg(feature = "bspwm")]
 fn from_query_result_to_id(
     query_result: Result<Vec<Id>, ReplyError>,
 ) -> Option<u32> {
@@ -171,7 +276,7 @@ fn from_query_result_to_id(
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "bspwm"))]
 mod tests {
     use super::*;
 
@@ -1,24 +1,39 @@
-use anyhow::bail;
+use anyhow::{bail, Context};
+use std::collections::HashMap;
+use std::fs;
 use std::path::Path;
 use std::string::String;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 
 use image::imageops::FilterType;
 use image::io::Reader as ImageReader;
-use image::{GenericImageView, RgbaImage};
+use image::RgbaImage;
+use resvg::tiny_skia;
+use resvg::usvg::{self, TreeParsing};
 
-use crate::config::WindowInfoType;
+use crate::config::{BarMatcher, WindowInfoType};
 use crate::core::WindowInfo;
+use crate::icon_theme;
 
 use x11rb::atom_manager;
 use x11rb::connection::Connection;
-use x11rb::protocol::randr::{self, ConnectionExt as _, GetCrtcInfoReply};
+#[cfg(feature = "x11")]
+use x11rb::connection::RequestConnection as _;
+use x11rb::properties::WmHints;
+use x11rb::protocol::randr::{self, ConnectionExt as _};
+use x11rb::protocol::shape::{self, ConnectionExt as _};
+use x11rb::protocol::xinerama::ConnectionExt as _;
 use x11rb::protocol::xproto::*;
+use x11rb::rust_connection::RustConnection;
 use x11rb::wrapper::ConnectionExt as _;
 
 atom_manager! {
     pub AtomCollection: AtomCollectionCookie {
         WM_PROTOCOLS,
         WM_DELETE_WINDOW,
+        WM_CHANGE_STATE,
         _NET_WM_NAME,
         _NET_WM_VISIBLE_NAME,
         WM_NAME,
@@ -29,51 +44,1377 @@ atom_manager! {
         _NET_SUPPORTING_WM_CHECK,
         _NET_WM_WINDOW_TYPE,
         _NET_WM_WINDOW_TYPE_DOCK,
+        _NET_WM_STRUT,
+        _NET_WM_STRUT_PARTIAL,
+        _NET_WM_STATE_HIDDEN,
+        _NET_WM_DESKTOP,
+        _NET_ACTIVE_WINDOW,
+        _NET_CURRENT_DESKTOP,
+        _NET_NUMBER_OF_DESKTOPS,
+        _NET_CLIENT_LIST,
     }
 }
 
+// WM_CLASS of icon windows, which has only instance
+const ICON_WM_INSTANCE: &str = "polybar-ixwindow-icon";
+
 struct ImageData {
     width: u32,
     height: u32,
     buf: Vec<u8>,
 }
 
-pub fn get_primary_monitor_name() -> anyhow::Result<String> {
-    let (conn, screen_num) = x11rb::connect(None)?;
-    let screen = &conn.setup().roots[screen_num];
+// The way icon is stored in cache and drawn on the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconStyle {
+    // Icon is flattened onto the background color of the bar and cached as
+    // jpg
+    Filled,
+
+    // Icon keeps its alpha channel: it is cached as png and drawn on window
+    // with 32-bit ARGB visual. This requires compositor to be running
+    Argb,
+
+    // Icon is cached as png and pixels with alpha below the threshold are cut
+    // out of the window with the X SHAPE extension
+    Shaped { alpha_threshold: u8 },
+
+    // Icon is cached as png and blended onto the pixels of the bar, that are
+    // under the icon, right before drawing
+    Sampled,
+}
+
+impl IconStyle {
+    pub fn extension(&self) -> &str {
+        match self {
+            Self::Filled => "jpg",
+            Self::Argb | Self::Shaped { .. } | Self::Sampled => "png",
+        }
+    }
+}
+
+// Window, that displays icons on the monitor, along with its server-side
+// resources, which have to be freed, when the window is destroyed
+#[derive(Debug, Clone)]
+pub struct IconWindow {
+    pub window: Window,
+
+    // Bar, which the window is put into, or None, if it's on the root window
+    pub parent: Option<Window>,
+
+    pixmap: Option<Pixmap>,
+    gc: Gcontext,
+    colormap: Option<Colormap>,
+    depth: u8,
+    width: u16,
+    height: u16,
+    mapped: bool,
+
+    // Pixels of the bar under the icon in sample mode. They are reused, until
+    // the bar or the icon moves
+    background: Option<SampledBackground>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SampledBackground {
+    source: BackgroundSource,
+    data: Vec<u8>,
+}
+
+// Drawable, that is sampled, with x, y, width and height of the rectangle on it
+type BackgroundSource = (Drawable, i16, i16, u16, u16);
+
+// X, y, width and height of the rectangle
+type Rectangle = (i16, i16, u16, u16);
 
-    let output_primary =
-        conn.randr_get_output_primary(screen.root)?.reply()?.output;
+// Window of the bar with its x, y, width and height relative to the root
+// window
+#[derive(Debug, Clone, Copy)]
+pub struct BarWindow {
+    pub window: Window,
+    geometry: (i16, i16, u16, u16),
+}
 
-    let output_primary_info =
-        conn.randr_get_output_info(output_primary, 0)?.reply()?;
+// Monitor, as it is seen by RandR or Xinerama
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MonitorInfo {
+    name: String,
+    primary: bool,
+    geometry: (i16, i16, u16, u16),
 
-    Ok(String::from_utf8(output_primary_info.name)?)
+    // Physical width and height in millimeters or zeros, if it's unknown
+    mm_size: (u32, u32),
 }
 
-fn get_monitor_crtc<Conn: Connection>(
+// Connection to the X server with resolved atoms, shared by all the
+// operations with X11
+pub struct XContext {
+    pub conn: RustConnection,
+    pub screen_num: usize,
+    pub atoms: AtomCollection,
+
+    // Number of monitors, that watch the properties of each window
+    watched_windows: Mutex<HashMap<Window, usize>>,
+}
+
+impl XContext {
+    // Connects to the given display or to $DISPLAY. Screen number of the
+    // display name (like `:0.1`) is respected by all the operations
+    pub fn new(display: Option<&str>) -> anyhow::Result<Self> {
+        let (conn, screen_num) = x11rb::connect(display)?;
+        let atoms = AtomCollection::new(&conn)?.reply()?;
+
+        Ok(Self {
+            conn,
+            screen_num,
+            atoms,
+            watched_windows: Mutex::default(),
+        })
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.conn.setup().roots[self.screen_num]
+    }
+
+    // Chooses the best style, that is supported by X server at the moment. If
+    // there is no compositor, then `fallback` is used
+    #[cfg(feature = "x11")]
+    pub fn detect_icon_style(&self, fallback: IconStyle) -> IconStyle {
+        let conn = &self.conn;
+        let screen = self.screen();
+        let compositor =
+            composite_manager_running(conn, self.screen_num).unwrap_or(false);
+
+        if compositor && find_argb_visual(screen).is_some() {
+            return IconStyle::Argb;
+        }
+
+        if let IconStyle::Shaped { .. } = fallback {
+            let shape_supported = matches!(
+                conn.extension_information(shape::X11_EXTENSION_NAME),
+                Ok(Some(_))
+            );
+
+            if !shape_supported {
+                return IconStyle::Filled;
+            }
+        }
+
+        fallback
+    }
+
+    // https://stackoverflow.com/questions/758648/find-the-name-of-the-x-window-manager
+    pub fn get_current_wm(&self) -> anyhow::Result<String> {
+        let conn = &self.conn;
+        let screen = self.screen();
+        let atoms = &self.atoms;
+
+        let property = conn
+            .get_property(
+                false,
+                screen.root,
+                atoms._NET_SUPPORTING_WM_CHECK,
+                AtomEnum::WINDOW,
+                0,
+                1024,
+            )?
+            .reply()?;
+
+        let Some(wm_window_id) = property.value32().and_then(|mut x| x.next())
+        else {
+            bail!("Window manager doesn't set _NET_SUPPORTING_WM_CHECK");
+        };
+
+        let property = conn
+            .get_property(
+                false,
+                wm_window_id,
+                atoms._NET_WM_NAME,
+                atoms.UTF8_STRING,
+                0,
+                1024,
+            )?
+            .reply()?;
+
+        let wm_name = String::from_utf8(property.value)?;
+
+        Ok(wm_name)
+    }
+
+    // Monitors are looked up in RandR 1.5 logical monitors, then in Xinerama
+    // screens and at last in RandR outputs. Source, which fails or is not
+    // supported by X server, is skipped
+    fn get_monitors(&self) -> impl Iterator<Item = Vec<MonitorInfo>> + '_ {
+        type Source =
+            fn(&RustConnection, Window) -> anyhow::Result<Vec<MonitorInfo>>;
+
+        let sources: [Source; 3] = [
+            get_randr_monitors,
+            get_xinerama_monitors,
+            get_output_monitors,
+        ];
+        let root = self.screen().root;
+
+        sources
+            .into_iter()
+            .filter_map(move |source| source(&self.conn, root).ok())
+            .filter(|monitors| !monitors.is_empty())
+    }
+
+    // Returns primary monitor or the first one, if none of them is primary
+    #[cfg(feature = "x11")]
+    pub fn get_primary_monitor_name(&self) -> anyhow::Result<String> {
+        let Some(monitors) = self.get_monitors().next() else {
+            bail!("Couldn't find any monitor");
+        };
+
+        let monitor =
+            monitors.iter().find(|x| x.primary).unwrap_or(&monitors[0]);
+
+        Ok(monitor.name.clone())
+    }
+
+    // Asks window manager to focus the window. Source is set to pager, so
+    // that window manager doesn't treat it as a request of the application
+    pub fn activate_window(&self, window: Window) -> anyhow::Result<()> {
+        let atom = self.atoms._NET_ACTIVE_WINDOW;
+
+        self.send_root_message(window, atom, [2, x11rb::CURRENT_TIME, 0, 0, 0])
+    }
+
+    // Asks window to close itself, if it supports WM_DELETE_WINDOW, and
+    // kills its client otherwise
+    #[cfg(feature = "x11")]
+    pub fn close_window(&self, window: Window) -> anyhow::Result<()> {
+        let conn = &self.conn;
+        let atoms = &self.atoms;
+
+        let protocols = conn
+            .get_property(
+                false,
+                window,
+                atoms.WM_PROTOCOLS,
+                AtomEnum::ATOM,
+                0,
+                1024,
+            )?
+            .reply()?;
+
+        let supports_delete = protocols
+            .value32()
+            .is_some_and(|mut x| x.any(|x| x == atoms.WM_DELETE_WINDOW));
+
+        if supports_delete {
+            let event = ClientMessageEvent::new(
+                32,
+                window,
+                atoms.WM_PROTOCOLS,
+                [atoms.WM_DELETE_WINDOW, x11rb::CURRENT_TIME, 0, 0, 0],
+            );
+
+            conn.send_event(false, window, EventMask::NO_EVENT, event)?;
+        } else {
+            conn.kill_client(window)?;
+        }
+
+        conn.flush()?;
+
+        Ok(())
+    }
+
+    // Iconifies window as ICCCM describes it
+    #[cfg(feature = "x11")]
+    pub fn minimize_window(&self, window: Window) -> anyhow::Result<()> {
+        const ICONIC_STATE: u32 = 3;
+
+        let atom = self.atoms.WM_CHANGE_STATE;
+
+        self.send_root_message(window, atom, [ICONIC_STATE, 0, 0, 0, 0])
+    }
+
+    #[cfg(feature = "x11")]
+    pub fn toggle_fullscreen(&self, window: Window) -> anyhow::Result<()> {
+        const TOGGLE: u32 = 2;
+
+        let atoms = &self.atoms;
+        let data = [TOGGLE, atoms._NET_WM_STATE_FULLSCREEN, 0, 2, 0];
+
+        self.send_root_message(window, atoms._NET_WM_STATE, data)
+    }
+
+    // Sends request about the window to the window manager, which listens
+    // to the client messages on the root window
+    fn send_root_message(
+        &self,
+        window: Window,
+        type_: Atom,
+        data: [u32; 5],
+    ) -> anyhow::Result<()> {
+        let event = ClientMessageEvent::new(32, window, type_, data);
+        let mask =
+            EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY;
+
+        self.conn
+            .send_event(false, self.screen().root, mask, event)?;
+        self.conn.flush()?;
+
+        Ok(())
+    }
+
+    // Subscribes to the changes of properties of the root window and to the
+    // windows, which are created, mapped and destroyed on it, like bars
+    #[cfg(feature = "x11")]
+    pub fn select_root_events(&self) -> anyhow::Result<()> {
+        let event_mask =
+            EventMask::PROPERTY_CHANGE | EventMask::SUBSTRUCTURE_NOTIFY;
+
+        self.conn
+            .change_window_attributes(
+                self.screen().root,
+                &ChangeWindowAttributesAux::default().event_mask(event_mask),
+            )?
+            .check()?;
+
+        Ok(())
+    }
+
+    // Subscribes to the changes of outputs: hotplug, resolution change,
+    // rotation and moving
+    #[cfg(feature = "x11")]
+    pub fn select_randr_events(&self) -> anyhow::Result<()> {
+        let mask = randr::NotifyMask::SCREEN_CHANGE
+            | randr::NotifyMask::CRTC_CHANGE
+            | randr::NotifyMask::OUTPUT_CHANGE;
+
+        self.conn
+            .randr_select_input(self.screen().root, mask)?
+            .check()?;
+
+        Ok(())
+    }
+
+    // Returns names of all monitors, that are turned on
+    pub fn get_monitor_names(&self) -> anyhow::Result<Vec<String>> {
+        let Some(monitors) = self.get_monitors().next() else {
+            bail!("Couldn't find any monitor");
+        };
+
+        Ok(monitors.into_iter().map(|x| x.name).collect())
+    }
+
+    // Returns x, y, width and height of the monitor. Name is searched in all
+    // the sources, because output can still be given by its name, when there
+    // are logical monitors
+    pub fn get_monitor_geometry(
+        &self,
+        monitor_name: &str,
+    ) -> anyhow::Result<(i16, i16, u16, u16)> {
+        for monitors in self.get_monitors() {
+            if let Some(monitor) =
+                monitors.into_iter().find(|x| x.name == monitor_name)
+            {
+                return Ok(monitor.geometry);
+            }
+        }
+
+        bail!("Couldn't find monitor {monitor_name}")
+    }
+
+    // Returns DPI of the monitor, calculated from its physical size. Diagonal
+    // is used, so that it doesn't matter, if the monitor is rotated
+    pub fn get_monitor_dpi(&self, monitor_name: &str) -> anyhow::Result<f32> {
+        for monitors in self.get_monitors() {
+            let Some(monitor) =
+                monitors.into_iter().find(|x| x.name == monitor_name)
+            else {
+                continue;
+            };
+
+            let (_, _, width, height) = monitor.geometry;
+            let (mm_width, mm_height) = monitor.mm_size;
+
+            // Projectors and some virtual outputs report zero size
+            if mm_width == 0 || mm_height == 0 {
+                continue;
+            }
+
+            let pixels = (width as f32).hypot(height as f32);
+            let inches = (mm_width as f32).hypot(mm_height as f32) / 25.4;
+
+            return Ok(pixels / inches);
+        }
+
+        bail!("Physical size of the monitor `{monitor_name}` is unknown");
+    }
+
+    // Returns `Xft.dpi` from the resource database, which is set with xrdb.
+    // It's always stored on the root window of the first screen
+    pub fn get_xft_dpi(&self) -> anyhow::Result<f32> {
+        let root = self.conn.setup().roots[0].root;
+        let property = self
+            .conn
+            .get_property(
+                false,
+                root,
+                AtomEnum::RESOURCE_MANAGER,
+                AtomEnum::STRING,
+                0,
+                u32::MAX,
+            )?
+            .reply()?;
+
+        parse_xft_dpi(&String::from_utf8_lossy(&property.value))
+            .context("Xft.dpi is not set")
+    }
+
+    // Returns bars, which top left corner is on the given monitor
+    pub fn get_bars(
+        &self,
+        bars: &[BarMatcher],
+        monitor_name: &str,
+    ) -> anyhow::Result<Vec<BarWindow>> {
+        let root = self.screen().root;
+        let monitor_geometry = self.get_monitor_geometry(monitor_name)?;
+        let children = self.conn.query_tree(root)?.reply()?.children;
+
+        Ok(children
+            .into_iter()
+            .filter_map(|window| {
+                self.bar_on_monitor(
+                    window,
+                    bars,
+                    monitor_name,
+                    monitor_geometry,
+                )
+            })
+            .collect())
+    }
+
+    // Returns the window, if it's the bar on the given monitor. Unlike
+    // `get_bars`, it doesn't query all the windows, so it's cheap to call on
+    // every new window
+    #[cfg(feature = "x11")]
+    pub fn get_bar(
+        &self,
+        window: Window,
+        bars: &[BarMatcher],
+        monitor_name: &str,
+    ) -> anyhow::Result<Option<BarWindow>> {
+        let monitor_geometry = self.get_monitor_geometry(monitor_name)?;
+
+        Ok(self.bar_on_monitor(window, bars, monitor_name, monitor_geometry))
+    }
+
+    fn bar_on_monitor(
+        &self,
+        window: Window,
+        bars: &[BarMatcher],
+        monitor_name: &str,
+        monitor_geometry: (i16, i16, u16, u16),
+    ) -> Option<BarWindow> {
+        let conn = &self.conn;
+        let root = self.screen().root;
+        let (monitor_x, monitor_y, monitor_width, monitor_height) =
+            monitor_geometry;
+
+        // Window may be destroyed at any moment, so errors just mean, that
+        // it's not a bar
+        let wm_instance_class = get_wm_instance_class(conn, window).ok()?;
+
+        // Icon windows are docks as well, but they are never bars
+        if wm_instance_class.0 == ICON_WM_INSTANCE {
+            return None;
+        }
+
+        let is_bar = bars
+            .iter()
+            .filter(|x| x.applies_to(monitor_name) && !x.is_empty())
+            .any(|x| {
+                self.is_bar(window, &wm_instance_class, x).unwrap_or(false)
+            });
+
+        if !is_bar {
+            return None;
+        }
+
+        let geometry = get_root_geometry(conn, root, window).ok()?;
+        let (x, y) = (geometry.0 as i32, geometry.1 as i32);
+        let (left, top) = (monitor_x as i32, monitor_y as i32);
+
+        let on_monitor = (left..left + monitor_width as i32).contains(&x)
+            && (top..top + monitor_height as i32).contains(&y);
+
+        on_monitor.then_some(BarWindow { window, geometry })
+    }
+
+    // Returns x, y, width and height of the window relative to the root window
+    #[cfg(feature = "ewmh")]
+    pub fn get_window_geometry(
+        &self,
+        window: Window,
+    ) -> anyhow::Result<(i16, i16, u16, u16)> {
+        get_root_geometry(&self.conn, self.screen().root, window)
+    }
+
+    // Returns x, y, width and height of the bar on the given monitor
+    pub fn get_bar_geometry(
+        &self,
+        bars: &[BarMatcher],
+        monitor_name: &str,
+    ) -> anyhow::Result<(i16, i16, u16, u16)> {
+        match self.get_bars(bars, monitor_name)?.first() {
+            Some(bar) => Ok(bar.geometry),
+            None => bail!("Couldn't find bar on the monitor {monitor_name}"),
+        }
+    }
+
+    fn is_bar(
+        &self,
+        window: Window,
+        wm_instance_class: &(String, String),
+        matcher: &BarMatcher,
+    ) -> anyhow::Result<bool> {
+        let conn = &self.conn;
+        let atoms = &self.atoms;
+        let (wm_instance, wm_class) = wm_instance_class;
+
+        let matches = |pattern: &Option<String>, value: &str| {
+            pattern.as_ref().is_none_or(|x| x == value)
+        };
+
+        if !matches(&matcher.instance, wm_instance)
+            || !matches(&matcher.class, wm_class)
+        {
+            return Ok(false);
+        }
+
+        if matcher.dock {
+            let window_type = conn
+                .get_property(
+                    false,
+                    window,
+                    atoms._NET_WM_WINDOW_TYPE,
+                    AtomEnum::ATOM,
+                    0,
+                    1024,
+                )?
+                .reply()?;
+
+            let is_dock = window_type.value32().is_some_and(|mut x| {
+                x.any(|x| x == atoms._NET_WM_WINDOW_TYPE_DOCK)
+            });
+
+            if !is_dock {
+                return Ok(false);
+            }
+        }
+
+        if matcher.strut {
+            let mut has_strut = false;
+
+            for atom in [atoms._NET_WM_STRUT_PARTIAL, atoms._NET_WM_STRUT] {
+                let strut = conn
+                    .get_property(
+                        false,
+                        window,
+                        atom,
+                        AtomEnum::CARDINAL,
+                        0,
+                        12,
+                    )?
+                    .reply()?;
+
+                has_strut |=
+                    strut.value32().is_some_and(|mut x| x.any(|x| x != 0));
+            }
+
+            if !has_strut {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn get_wm_class(&self, wid: u32) -> anyhow::Result<String> {
+        let conn = &self.conn;
+
+        let property = conn
+            .get_property(
+                false,
+                wid,
+                AtomEnum::WM_CLASS,
+                AtomEnum::STRING,
+                0,
+                1024,
+            )?
+            .reply()?;
+
+        let mut iter = property.value.split(|x| *x == 0);
+        let wm_class = iter.next();
+        let wm_instance = iter.next();
+
+        if let Some(bytes) = wm_instance {
+            return Ok(String::from_utf8(bytes.to_vec())?);
+        }
+
+        if let Some(bytes) = wm_class {
+            return Ok(String::from_utf8(bytes.to_vec())?);
+        }
+
+        Ok(String::new())
+    }
+
+    pub fn get_window_info(
+        &self,
+        window_id: u32,
+        info_types: &[WindowInfoType],
+    ) -> anyhow::Result<WindowInfo> {
+        let conn = &self.conn;
+        let atoms = &self.atoms;
+
+        for info_type in info_types {
+            let info_bytes = match info_type {
+                WindowInfoType::WmClass => {
+                    let property = conn
+                        .get_property(
+                            false,
+                            window_id,
+                            AtomEnum::WM_CLASS,
+                            AtomEnum::STRING,
+                            0,
+                            1024,
+                        )?
+                        .reply()?;
+
+                    let mut iter = property.value.split(|x| *x == 0);
+                    let wm_class = iter.next();
+
+                    wm_class.map(|x| x.to_vec())
+                }
+
+                WindowInfoType::WmInstance => {
+                    let property = conn
+                        .get_property(
+                            false,
+                            window_id,
+                            AtomEnum::WM_CLASS,
+                            AtomEnum::STRING,
+                            0,
+                            1024,
+                        )?
+                        .reply()?;
+
+                    let mut iter = property.value.split(|x| *x == 0);
+                    let wm_instance = iter.nth(1);
+
+                    wm_instance.map(|x| x.to_vec())
+                }
+
+                WindowInfoType::NetWmName => {
+                    let property = conn
+                        .get_property(
+                            false,
+                            window_id,
+                            atoms._NET_WM_NAME,
+                            atoms.UTF8_STRING,
+                            0,
+                            1024,
+                        )?
+                        .reply()?;
+
+                    let wm_name = property.value;
+
+                    Some(wm_name)
+                }
+
+                WindowInfoType::NetWmVisibleName => {
+                    let property = conn
+                        .get_property(
+                            false,
+                            window_id,
+                            atoms._NET_WM_VISIBLE_NAME,
+                            atoms.UTF8_STRING,
+                            0,
+                            1024,
+                        )?
+                        .reply()?;
+
+                    let wm_name = property.value;
+
+                    Some(wm_name)
+                }
+
+                WindowInfoType::WmName => {
+                    let property = conn
+                        .get_property(
+                            false,
+                            window_id,
+                            AtomEnum::WM_NAME,
+                            AtomEnum::STRING,
+                            0,
+                            1024,
+                        )?
+                        .reply()?;
+
+                    let wm_name = property.value;
+
+                    Some(wm_name)
+                }
+            };
+
+            if let Some(bytes) = info_bytes {
+                if !bytes.is_empty() {
+                    return Ok(WindowInfo {
+                        info: String::from_utf8_lossy(&bytes).to_string(),
+                        info_type: info_type.to_owned(),
+                    });
+                }
+            }
+        }
+
+        Ok(WindowInfo {
+            info: String::new(),
+            info_type: info_types.last().unwrap().to_owned(),
+        })
+    }
+
+    pub fn is_window_fullscreen(&self, window_id: u32) -> anyhow::Result<bool> {
+        let conn = &self.conn;
+        let atoms = &self.atoms;
+
+        let property = conn
+            .get_property(
+                false,
+                window_id,
+                atoms._NET_WM_STATE,
+                AtomEnum::ATOM,
+                0,
+                1024,
+            )?
+            .reply()?;
+
+        let data = property.value;
+
+        if data.is_empty() {
+            return Ok(false);
+        }
+
+        // Read property value by chunks of 4 bytes, because there might be more,
+        // than one Atom specified in _NET_WM_STATE
+        Ok(data.chunks_exact(4).fold(false, |acc, chunk| {
+            let net_wm_state_atom =
+                u32::from_le_bytes(chunk.try_into().unwrap());
+
+            acc || (net_wm_state_atom == atoms._NET_WM_STATE_FULLSCREEN)
+        }))
+    }
+
+    // Enables or disables PropertyNotify events for the window. Monitors
+    // share the connection, so events are enabled by the first monitor, that
+    // watches the window, and disabled by the last one
+    pub fn set_property_events(
+        &self,
+        window_id: u32,
+        enabled: bool,
+    ) -> anyhow::Result<()> {
+        let conn = &self.conn;
+        let mut watched_windows = self.watched_windows.lock().unwrap();
+        let count = watched_windows.get(&window_id).copied().unwrap_or(0);
+        let new_count = if enabled {
+            count + 1
+        } else {
+            count.saturating_sub(1)
+        };
+
+        if new_count == 0 {
+            watched_windows.remove(&window_id);
+        } else {
+            watched_windows.insert(window_id, new_count);
+        }
+
+        let event_mask = match (count, new_count) {
+            (0, 1) => EventMask::PROPERTY_CHANGE,
+            (1, 0) => EventMask::NO_EVENT,
+            _ => return Ok(()),
+        };
+
+        conn.change_window_attributes(
+            window_id,
+            &ChangeWindowAttributesAux::default().event_mask(event_mask),
+        )?;
+        conn.flush()?;
+
+        Ok(())
+    }
+
+    // Creates unmapped icon window, which is reused for all the icons, that
+    // are displayed on the monitor
+    pub fn create_icon_window(
+        &self,
+        style: IconStyle,
+    ) -> anyhow::Result<IconWindow> {
+        let conn = &self.conn;
+        let screen = self.screen();
+        let wm_class = ICON_WM_INSTANCE.as_bytes();
+        let win = conn.generate_id()?;
+        let atoms = &self.atoms;
+
+        let mut window_args =
+            CreateWindowAux::default().override_redirect(1).event_mask(
+                EventMask::EXPOSURE
+                    | EventMask::STRUCTURE_NOTIFY
+                    | EventMask::BUTTON_PRESS,
+            );
+        let mut colormap = None;
+
+        let (depth, visual) = match style {
+            IconStyle::Filled
+            | IconStyle::Shaped { .. }
+            | IconStyle::Sampled => (screen.root_depth, screen.root_visual),
+
+            IconStyle::Argb => {
+                let Some(visual) = find_argb_visual(screen) else {
+                    bail!("Couldn't find 32-bit visual for transparent icon");
+                };
+
+                // Window with a visual, different from its parent's, must have
+                // its own colormap and border pixel, otherwise X server will
+                // respond with BadMatch
+                let argb_colormap = conn.generate_id()?;
+                conn.create_colormap(
+                    ColormapAlloc::NONE,
+                    argb_colormap,
+                    screen.root,
+                    visual,
+                )?;
+
+                window_args = window_args
+                    .colormap(argb_colormap)
+                    .border_pixel(0)
+                    .background_pixel(0);
+                colormap = Some(argb_colormap);
+
+                (32, visual)
+            }
+        };
+
+        // Real geometry is set, when the icon is drawn
+        conn.create_window(
+            depth,
+            win,
+            screen.root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            visual,
+            &window_args,
+        )?;
+
+        conn.change_property32(
+            PropMode::REPLACE,
+            win,
+            atoms._NET_WM_WINDOW_TYPE,
+            AtomEnum::ATOM,
+            &[atoms._NET_WM_WINDOW_TYPE_DOCK],
+        )?;
+
+        conn.change_property(
+            PropMode::REPLACE,
+            win,
+            AtomEnum::WM_CLASS,
+            AtomEnum::STRING,
+            8,
+            wm_class.len() as u32,
+            wm_class,
+        )?;
+
+        let gc = conn.generate_id()?;
+        create_gc(conn, gc, win, &CreateGCAux::new())?;
+        conn.flush()?;
+
+        Ok(IconWindow {
+            window: win,
+            parent: None,
+            pixmap: None,
+            gc,
+            colormap,
+            depth,
+            width: 0,
+            height: 0,
+            mapped: false,
+            background: None,
+        })
+    }
+
+    // Draws the icon on the icon window and shows it. New icon is drawn on a
+    // separate pixmap, so the old one stays on the screen until the new one
+    // is ready
+    #[allow(clippy::too_many_arguments)]
+    pub fn display_icon(
+        &self,
+        icon_window: &mut IconWindow,
+        image_path: &Path,
+        x: i16,
+        y: i16,
+        size: u16,
+        monitor_name: &str,
+        bars: &[BarWindow],
+        reparent: bool,
+        style: IconStyle,
+        color: &str,
+    ) -> anyhow::Result<()> {
+        let conn = &self.conn;
+        let image = load_icon_image(image_path, size)?;
+        let (width, height) = image.dimensions();
+
+        // Converting to u16, because it is required later by x11rb
+        let (width, height) = (width as u16, height as u16);
+        let mut data = image.into_raw();
+
+        // Custom icons may be transparent even in filled style, so we fill them
+        // here
+        if style == IconStyle::Filled {
+            fill_background(&mut data, color);
+        }
+
+        let (monitor_x, monitor_y, _, _) =
+            self.get_monitor_geometry(monitor_name)?;
+        let (icon_x, icon_y) = (monitor_x + x, monitor_y + y);
+        let win = icon_window.window;
+
+        if style == IconStyle::Sampled {
+            let root = self.screen().root;
+            let source = find_background_source(
+                root, bars, icon_x, icon_y, width, height,
+            );
+
+            let cached = icon_window
+                .background
+                .as_ref()
+                .filter(|x| x.source == source);
+
+            let background = match cached {
+                Some(x) => x.data.clone(),
+                None => {
+                    let data = self.sample_background(icon_window, source)?;
+                    icon_window.background = Some(SampledBackground {
+                        source,
+                        data: data.clone(),
+                    });
+
+                    data
+                }
+            };
+
+            blend_onto_background(&mut data, &background);
+        }
+
+        // ARGB visual expects colors to be premultiplied by alpha
+        if style == IconStyle::Argb {
+            premultiply_alpha(&mut data);
+        }
+
+        // Swapping blue and red colors so that icon will be displayed with normal
+        // colors
+        data.chunks_exact_mut(4).for_each(|chunk| {
+            let (c0, c2) = (chunk[0], chunk[2]);
+            chunk[2] = c0;
+            chunk[0] = c2;
+        });
+
+        let pixmap = conn.generate_id()?;
+        conn.create_pixmap(icon_window.depth, pixmap, win, width, height)?;
+
+        conn.put_image(
+            ImageFormat::Z_PIXMAP,
+            pixmap,
+            icon_window.gc,
+            width,
+            height,
+            0,
+            0,
+            0,
+            icon_window.depth,
+            &data,
+        )?;
+
+        // Icon, that is put into the bar, moves, hides and is restacked
+        // together with it, and its coordinates are relative to the bar
+        let parent = bars.first().filter(|_| reparent);
+        let (position_x, position_y) = match parent {
+            Some(bar) => (icon_x - bar.geometry.0, icon_y - bar.geometry.1),
+            None => (icon_x, icon_y),
+        };
+
+        if icon_window.parent != parent.map(|x| x.window) {
+            let root = self.screen().root;
+            let new_parent = parent.map_or(root, |x| x.window);
+
+            conn.reparent_window(win, new_parent, position_x, position_y)?;
+            icon_window.parent = parent.map(|x| x.window);
+        }
+
+        let window_conf = ConfigureWindowAux::default()
+            .x(position_x as i32)
+            .y(position_y as i32)
+            .width(width as u32)
+            .height(height as u32)
+            .stack_mode(if parent.is_some() {
+                StackMode::ABOVE
+            } else {
+                StackMode::BELOW
+            });
+
+        conn.configure_window(win, &window_conf)?;
+
+        if parent.is_none() {
+            put_icon_over_bars(conn, bars, win)?;
+        }
+
+        if let IconStyle::Shaped { alpha_threshold } = style {
+            shape_icon_window(
+                conn,
+                win,
+                &data,
+                width,
+                height,
+                alpha_threshold,
+            )?;
+        }
+
+        // Pixmap is set as the background of the window, so that X server
+        // repaints the icon by itself, when it gets covered by other windows
+        conn.change_window_attributes(
+            win,
+            &ChangeWindowAttributesAux::default().background_pixmap(pixmap),
+        )?;
+        conn.clear_area(false, win, 0, 0, 0, 0)?;
+
+        if !icon_window.mapped {
+            conn.map_window(win)?;
+            icon_window.mapped = true;
+        }
+
+        if let Some(old_pixmap) = icon_window.pixmap {
+            conn.free_pixmap(old_pixmap)?;
+        }
+
+        icon_window.pixmap = Some(pixmap);
+        icon_window.width = width;
+        icon_window.height = height;
+        conn.flush()?;
+
+        Ok(())
+    }
+
+    // Draws icon on its window again, used when handling Expose events
+    #[cfg(feature = "x11")]
+    pub fn repaint_icon(&self, icon_window: &IconWindow) -> anyhow::Result<()> {
+        let Some(pixmap) = icon_window.pixmap else {
+            return Ok(());
+        };
+
+        self.conn.copy_area(
+            pixmap,
+            icon_window.window,
+            icon_window.gc,
+            0,
+            0,
+            0,
+            0,
+            icon_window.width,
+            icon_window.height,
+        )?;
+        self.conn.flush()?;
+
+        Ok(())
+    }
+
+    pub fn hide_icon(
+        &self,
+        icon_window: &mut IconWindow,
+    ) -> anyhow::Result<()> {
+        if icon_window.mapped {
+            self.conn.unmap_window(icon_window.window)?;
+            self.conn.flush()?;
+            icon_window.mapped = false;
+        }
+
+        Ok(())
+    }
+
+    // Samples the bar under the new position of the icon. Pixels, that are
+    // covered by the icon, can't be read from the bar, so they are taken from
+    // the previous sample. If they are not there, the icon is hidden, until
+    // the bar repaints them
+    fn sample_background(
+        &self,
+        icon_window: &mut IconWindow,
+        source: BackgroundSource,
+    ) -> anyhow::Result<Vec<u8>> {
+        let conn = &self.conn;
+        let (drawable, x, y, width, height) = source;
+        let area = (x, y, width, height);
+
+        let covered = if icon_window.mapped {
+            let position = conn
+                .translate_coordinates(icon_window.window, drawable, 0, 0)?
+                .reply()?;
+            let icon_area = (
+                position.dst_x,
+                position.dst_y,
+                icon_window.width,
+                icon_window.height,
+            );
+
+            intersect_rectangles(icon_area, area)
+        } else {
+            None
+        };
+
+        let Some(covered) = covered else {
+            return sample_bar_background(conn, source);
+        };
+
+        let previous = icon_window.background.as_ref().filter(|x| {
+            let (previous_drawable, x, y, width, height) = x.source;
+
+            previous_drawable == drawable
+                && intersect_rectangles((x, y, width, height), covered)
+                    == Some(covered)
+        });
+
+        match previous {
+            Some(previous) => {
+                let (_, x, y, width, height) = previous.source;
+                let mut data = sample_bar_background(conn, source)?;
+
+                copy_rectangle(
+                    &previous.data,
+                    (x, y, width, height),
+                    &mut data,
+                    area,
+                    covered,
+                );
+
+                Ok(data)
+            }
+
+            None => {
+                conn.unmap_window(icon_window.window)?;
+                conn.flush()?;
+                icon_window.mapped = false;
+
+                thread::sleep(Duration::from_millis(50)); // let bar be repainted
+                sample_bar_background(conn, source)
+            }
+        }
+    }
+
+    // Destroys icon window and frees all the resources, allocated for it
+    pub fn destroy_icon(&self, icon_window: &IconWindow) -> anyhow::Result<()> {
+        self.conn.destroy_window(icon_window.window)?;
+        self.free_icon_resources(icon_window)
+    }
+
+    // Frees resources of the icon window, which is already destroyed, for
+    // example, together with the bar, it was put into
+    pub fn free_icon_resources(
+        &self,
+        icon_window: &IconWindow,
+    ) -> anyhow::Result<()> {
+        let conn = &self.conn;
+
+        conn.free_gc(icon_window.gc)?;
+
+        if let Some(pixmap) = icon_window.pixmap {
+            conn.free_pixmap(pixmap)?;
+        }
+
+        if let Some(colormap) = icon_window.colormap {
+            conn.free_colormap(colormap)?;
+        }
+
+        conn.flush()?;
+
+        Ok(())
+    }
+
+    pub fn generate_icon(
+        &self,
+        icon_name: &str,
+        cache_dir: &Path,
+        color: &str,
+        window_id: u32,
+        style: IconStyle,
+    ) -> anyhow::Result<()> {
+        let conn = &self.conn;
+        let atoms = &self.atoms;
+
+        let icon = match get_net_wm_icon(conn, atoms, window_id)? {
+            Some(icon) => icon,
+
+            // Old X clients don't set _NET_WM_ICON, but they may provide icon in
+            // WM_HINTS instead
+            None => match get_wm_hints_icon(conn, window_id)? {
+                Some(icon) => icon,
+                None => bail!("No icon was found for this window"),
+            },
+        };
+
+        save_icon(&icon, icon_name, cache_dir, color, style)
+    }
+
+    // Generates icon from the icon theme for windows, that don't have
+    // _NET_WM_ICON set, by matching their WM_CLASS with .desktop files
+    pub fn generate_icon_from_theme(
+        &self,
+        icon_name: &str,
+        cache_dir: &Path,
+        color: &str,
+        window_id: u32,
+        style: IconStyle,
+        size: u16,
+    ) -> anyhow::Result<()> {
+        let conn = &self.conn;
+        let (wm_instance, wm_class) = get_wm_instance_class(conn, window_id)?;
+
+        let Some(theme_icon) =
+            icon_theme::find_app_icon(&wm_instance, &wm_class, size)
+        else {
+            bail!("No icon was found in the icon theme for this window");
+        };
+
+        // Svg icons are copied as is, because they are rendered at the exact size
+        // of the icon, when displaying it
+        if theme_icon.extension().is_some_and(|x| x == "svg") {
+            fs::copy(theme_icon, cache_dir.join(format!("{icon_name}.svg")))?;
+
+            return Ok(());
+        }
+
+        let image = ImageReader::open(theme_icon)?.decode()?.into_rgba8();
+        let icon = ImageData {
+            width: image.width(),
+            height: image.height(),
+            buf: image.into_raw(),
+        };
+
+        save_icon(&icon, icon_name, cache_dir, color, style)
+    }
+}
+
+fn find_argb_visual(screen: &Screen) -> Option<Visualid> {
+    for depth in &screen.allowed_depths {
+        if depth.depth != 32 {
+            continue;
+        }
+
+        for visual in &depth.visuals {
+            if visual.class == VisualClass::TRUE_COLOR {
+                return Some(visual.visual_id);
+            }
+        }
+    }
+
+    None
+}
+
+// Logical monitors of RandR 1.5. Besides outputs, they include monitors,
+// created with `xrandr --setmonitor` (for example, halves of one ultrawide
+// screen)
+fn get_randr_monitors<Conn: Connection>(
     conn: &Conn,
-    monitor_name: &str,
-) -> anyhow::Result<GetCrtcInfoReply> {
-    let screen = &conn.setup().roots[0];
-    let resources = conn
-        .randr_get_screen_resources_current(screen.root)?
-        .reply()?;
+    root: Window,
+) -> anyhow::Result<Vec<MonitorInfo>> {
+    let reply = conn.randr_get_monitors(root, true)?.reply()?;
+    let mut monitors = Vec::new();
+
+    for monitor in reply.monitors {
+        let name = conn.get_atom_name(monitor.name)?.reply()?.name;
+
+        monitors.push(MonitorInfo {
+            name: String::from_utf8(name)?,
+            primary: monitor.primary,
+            geometry: (monitor.x, monitor.y, monitor.width, monitor.height),
+            mm_size: (
+                monitor.width_in_millimeters,
+                monitor.height_in_millimeters,
+            ),
+        });
+    }
+
+    Ok(monitors)
+}
+
+// Screens of Xinerama (for example, with NVIDIA TwinView), which don't have
+// names, so they are named by their index: 0, 1 and so on
+fn get_xinerama_monitors<Conn: Connection>(
+    conn: &Conn,
+    _root: Window,
+) -> anyhow::Result<Vec<MonitorInfo>> {
+    if conn.xinerama_is_active()?.reply()?.state == 0 {
+        return Ok(Vec::new());
+    }
+
+    let reply = conn.xinerama_query_screens()?.reply()?;
+
+    Ok(reply
+        .screen_info
+        .into_iter()
+        .enumerate()
+        .map(|(i, screen)| MonitorInfo {
+            name: i.to_string(),
+            primary: i == 0,
+            geometry: (screen.x_org, screen.y_org, screen.width, screen.height),
+            mm_size: (0, 0),
+        })
+        .collect())
+}
+
+// Connected RandR outputs, that are turned on, with geometry of their CRTCs
+fn get_output_monitors<Conn: Connection>(
+    conn: &Conn,
+    root: Window,
+) -> anyhow::Result<Vec<MonitorInfo>> {
+    let resources = conn.randr_get_screen_resources_current(root)?.reply()?;
+    let primary = conn.randr_get_output_primary(root)?.reply()?.output;
+    let mut monitors = Vec::new();
 
     for output in resources.outputs {
         let output_info = conn.randr_get_output_info(output, 0)?.reply()?;
 
-        if std::str::from_utf8(&output_info.name)? == monitor_name {
-            let crtc_info =
-                conn.randr_get_crtc_info(output_info.crtc, 0)?.reply()?;
-
-            if output_info.connection == randr::Connection::CONNECTED {
-                return Ok(crtc_info);
-            }
+        if output_info.connection != randr::Connection::CONNECTED
+            || output_info.crtc == x11rb::NONE
+        {
+            continue;
         }
+
+        let crtc = conn.randr_get_crtc_info(output_info.crtc, 0)?.reply()?;
+
+        monitors.push(MonitorInfo {
+            name: String::from_utf8(output_info.name)?,
+            primary: output == primary,
+            geometry: (crtc.x, crtc.y, crtc.width, crtc.height),
+            mm_size: (output_info.mm_width, output_info.mm_height),
+        });
     }
 
-    bail!("Couldn't get given monitor CRTC")
+    Ok(monitors)
+}
+
+fn parse_xft_dpi(resources: &str) -> Option<f32> {
+    resources.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+
+        (key.trim() == "Xft.dpi")
+            .then(|| value.trim().parse().ok())
+            .flatten()
+    })
 }
 
 fn get_wm_instance_class<Conn: Connection>(
@@ -110,352 +1451,329 @@ fn get_wm_instance_class<Conn: Connection>(
     Ok((wm_instance, wm_class))
 }
 
-fn get_polybar_ids<Conn: Connection>(
-    conn: &Conn,
-) -> anyhow::Result<Vec<Window>> {
-    let screen = &conn.setup().roots[0];
-    let all_windows = conn.query_tree(screen.root)?.reply()?.children;
-    let mut polybar_ids = Vec::new();
-
-    for window in all_windows {
-        let (wm_instance, wm_class) = get_wm_instance_class(conn, window)?;
-
-        if (wm_instance.as_str(), wm_class.as_str()) == ("polybar", "Polybar") {
-            polybar_ids.push(window);
-        }
-
-        // println!("wm_class: {wm_class}; wm_instance: {wm_instance}");
-    }
-
-    Ok(polybar_ids)
-}
-
-fn put_icon_over_polybar<Conn: Connection>(
+// Returns x and y of the window relative to the root window, and its width
+// and height
+fn get_root_geometry<Conn: Connection>(
     conn: &Conn,
+    root: Window,
     window: Window,
-) -> anyhow::Result<()> {
-    let polybar_ids = get_polybar_ids(conn)?;
-    let mut window_conf;
-
-    for polybar_id in polybar_ids {
-        window_conf = ConfigureWindowAux::default()
-            .stack_mode(StackMode::ABOVE)
-            .sibling(polybar_id);
-
-        conn.configure_window(window, &window_conf)?;
-    }
-
-    Ok(())
+) -> anyhow::Result<(i16, i16, u16, u16)> {
+    let geometry = conn.get_geometry(window)?.reply()?;
+    let position = conn.translate_coordinates(window, root, 0, 0)?.reply()?;
+
+    Ok((
+        position.dst_x,
+        position.dst_y,
+        geometry.width,
+        geometry.height,
+    ))
 }
 
-pub fn display_icon<Conn: Connection>(
+fn put_icon_over_bars<Conn: Connection>(
     conn: &Conn,
-    image_path: &Path,
-    x: i16,
-    y: i16,
-    size: u16,
-    monitor_name: &str,
-) -> anyhow::Result<Window> {
-    let image = ImageReader::open(image_path)?.decode()?;
-    let image = image.resize(size as u32, size as u32, FilterType::CatmullRom);
-    let (width, height) = image.dimensions();
-
-    // Converting to u16, because it is required later by x11rb
-    let (width, height) = (width as u16, height as u16);
-
-    let screen = &conn.setup().roots[0];
-    let monitor_crtc = get_monitor_crtc(conn, monitor_name)?;
-    let wm_class = b"polybar-ixwindow-icon";
-    let win = conn.generate_id()?;
-    let atoms = AtomCollection::new(conn)?.reply()?;
-
-    let window_conf =
-        ConfigureWindowAux::default().stack_mode(StackMode::BELOW);
-
-    let window_args = CreateWindowAux::default()
-        .override_redirect(1)
-        .event_mask(EventMask::EXPOSURE);
-
-    conn.create_window(
-        x11rb::COPY_FROM_PARENT as u8,
-        win,
-        screen.root,
-        monitor_crtc.x + x,
-        monitor_crtc.y + y,
-        width,
-        height,
-        0,
-        WindowClass::COPY_FROM_PARENT,
-        screen.root_visual,
-        &window_args,
-    )?;
+    bars: &[BarWindow],
+    window: Window,
+) -> anyhow::Result<()> {
+    let mut window_conf;
 
-    conn.configure_window(win, &window_conf)?;
-    put_icon_over_polybar(conn, win)?;
+    for bar in bars {
+        window_conf = ConfigureWindowAux::default()
+            .stack_mode(StackMode::ABOVE)
+            .sibling(bar.window);
 
-    conn.change_property32(
-        PropMode::REPLACE,
-        win,
-        atoms._NET_WM_WINDOW_TYPE,
-        AtomEnum::ATOM,
-        &[atoms._NET_WM_WINDOW_TYPE_DOCK],
-    )?;
-    conn.map_window(win)?;
-    conn.flush()?;
+        conn.configure_window(window, &window_conf)?;
+    }
 
-    conn.change_property(
-        PropMode::REPLACE,
-        win,
-        AtomEnum::WM_CLASS,
-        AtomEnum::STRING,
-        8,
-        wm_class.len() as u32,
-        wm_class,
-    )?;
+    Ok(())
+}
+
+// Cuts out pixels of the window, which alpha is below the threshold, so that
+// the bar is seen through them
+fn shape_icon_window<Conn: Connection>(
+    conn: &Conn,
+    win: Window,
+    data: &[u8],
+    width: u16,
+    height: u16,
+    alpha_threshold: u8,
+) -> anyhow::Result<()> {
+    // Z_PIXMAP images are padded as pixmaps of their depth, which can differ
+    // from the padding of bitmaps
+    let setup = conn.setup();
+    let Some(format) = setup.pixmap_formats.iter().find(|x| x.depth == 1)
+    else {
+        bail!("Unknown format of pixmap with depth 1");
+    };
 
-    let gc_aux = CreateGCAux::new();
-    let gc = conn.generate_id()?;
-    create_gc(conn, gc, win, &gc_aux)?;
+    let mask = build_shape_mask(
+        data,
+        width as usize,
+        alpha_threshold,
+        format.scanline_pad as usize,
+        setup.bitmap_format_bit_order == ImageOrder::LSB_FIRST,
+    );
 
-    let pixmap = conn.generate_id()?;
-    conn.create_pixmap(screen.root_depth, pixmap, win, width, height)?;
+    let mask_pixmap = conn.generate_id()?;
+    conn.create_pixmap(1, mask_pixmap, win, width, height)?;
 
-    // Swapping blue and red colors so that icon will be displayed with normal
-    // colors
-    let mut data = image.into_rgba8().into_raw();
-    data.chunks_exact_mut(4).for_each(|chunk| {
-        let (c0, c2) = (chunk[0], chunk[2]);
-        chunk[2] = c0;
-        chunk[0] = c2;
-    });
+    // GC should have the same depth as the drawable it's used with, so we
+    // can't reuse the one, created for the window
+    let mask_gc = conn.generate_id()?;
+    create_gc(conn, mask_gc, mask_pixmap, &CreateGCAux::new())?;
 
     conn.put_image(
         ImageFormat::Z_PIXMAP,
-        pixmap,
-        gc,
+        mask_pixmap,
+        mask_gc,
         width,
         height,
         0,
         0,
         0,
-        screen.root_depth,
-        &data,
+        1,
+        &mask,
+    )?;
+
+    conn.shape_mask(
+        shape::SO::SET,
+        shape::SK::BOUNDING,
+        win,
+        0,
+        0,
+        mask_pixmap,
     )?;
 
-    conn.copy_area(pixmap, win, gc, 0, 0, 0, 0, width, height)?;
-    conn.flush()?;
+    conn.free_gc(mask_gc)?;
+    conn.free_pixmap(mask_pixmap)?;
 
-    Ok(win)
+    Ok(())
 }
 
-// https://stackoverflow.com/questions/758648/find-the-name-of-the-x-window-manager
-pub fn get_current_wm() -> anyhow::Result<String> {
-    let (conn, screen_num) = x11rb::connect(None)?;
-    let screen = &conn.setup().roots[screen_num];
-    let atoms = AtomCollection::new(&conn)?.reply()?;
+// Builds 1-bit bitmap from alpha channel of RGBA data, where every row is
+// padded to `scanline_pad` bits
+fn build_shape_mask(
+    data: &[u8],
+    width: usize,
+    alpha_threshold: u8,
+    scanline_pad: usize,
+    lsb_first: bool,
+) -> Vec<u8> {
+    let stride = image_stride(width, 1, scanline_pad);
+    let height = data.len() / 4 / width.max(1);
+    let mut mask = vec![0u8; stride * height];
+
+    for (i, pixel) in data.chunks_exact(4).enumerate() {
+        if pixel[3] < alpha_threshold {
+            continue;
+        }
 
-    let property = conn
-        .get_property(
-            false,
-            screen.root,
-            atoms._NET_SUPPORTING_WM_CHECK,
-            AtomEnum::WINDOW,
-            0,
-            1024,
-        )?
-        .reply()?;
+        let (x, y) = (i % width, i / width);
+        let bit = if lsb_first { x % 8 } else { 7 - x % 8 };
 
-    let wm_window_id = property.value32().unwrap().next().unwrap();
+        mask[y * stride + x / 8] |= 1 << bit;
+    }
 
-    let property = conn
-        .get_property(
-            false,
-            wm_window_id,
-            atoms._NET_WM_NAME,
-            atoms.UTF8_STRING,
-            0,
-            1024,
-        )?
-        .reply()?;
+    mask
+}
 
-    let wm_name = String::from_utf8(property.value)?;
+// Returns the bar, that contains the given rectangle, with the rectangle
+// relative to it. If there is no bar there, then the root window is returned
+fn find_background_source(
+    root: Window,
+    bars: &[BarWindow],
+    x: i16,
+    y: i16,
+    width: u16,
+    height: u16,
+) -> BackgroundSource {
+    for bar in bars {
+        let (bar_x, bar_y, bar_width, bar_height) = bar.geometry;
+        let contains_icon = bar_x <= x
+            && bar_y <= y
+            && x + width as i16 <= bar_x + bar_width as i16
+            && y + height as i16 <= bar_y + bar_height as i16;
+
+        if contains_icon {
+            return (bar.window, x - bar_x, y - bar_y, width, height);
+        }
+    }
 
-    Ok(wm_name)
+    (root, x, y, width, height)
 }
 
-pub fn get_wm_class(wid: u32) -> anyhow::Result<String> {
-    let (conn, _) = x11rb::connect(None)?;
-
-    let property = conn
-        .get_property(
-            false,
-            wid,
-            AtomEnum::WM_CLASS,
-            AtomEnum::STRING,
-            0,
-            1024,
+// Grabs pixels of the bar, that are under the given rectangle
+fn sample_bar_background<Conn: Connection>(
+    conn: &Conn,
+    source: BackgroundSource,
+) -> anyhow::Result<Vec<u8>> {
+    let (drawable, src_x, src_y, width, height) = source;
+    let image = conn
+        .get_image(
+            ImageFormat::Z_PIXMAP,
+            drawable,
+            src_x,
+            src_y,
+            width,
+            height,
+            !0,
         )?
         .reply()?;
 
-    let mut iter = property.value.split(|x| *x == 0);
-    let wm_class = iter.next();
-    let wm_instance = iter.next();
-
-    if let Some(bytes) = wm_instance {
-        return Ok(String::from_utf8(bytes.to_vec())?);
-    }
-
-    if let Some(bytes) = wm_class {
-        return Ok(String::from_utf8(bytes.to_vec())?);
+    // We can only blend onto 24 and 32 depth windows, which use 4 bytes per
+    // pixel
+    if image.data.len() != width as usize * height as usize * 4 {
+        bail!("Unsupported depth of the bar window: {}", image.depth);
     }
 
-    Ok(String::new())
+    Ok(image.data)
 }
 
-pub fn get_window_info(
-    window_id: u32,
-    info_types: &[WindowInfoType],
-) -> anyhow::Result<WindowInfo> {
-    let (conn, _) = x11rb::connect(None)?;
-    let atoms = AtomCollection::new(&conn)?.reply()?;
-
-    for info_type in info_types {
-        let info_bytes = match info_type {
-            WindowInfoType::WmClass => {
-                let property = conn
-                    .get_property(
-                        false,
-                        window_id,
-                        AtomEnum::WM_CLASS,
-                        AtomEnum::STRING,
-                        0,
-                        1024,
-                    )?
-                    .reply()?;
-
-                let mut iter = property.value.split(|x| *x == 0);
-                let wm_class = iter.next();
-
-                wm_class.map(|x| x.to_vec())
-            }
-
-            WindowInfoType::WmInstance => {
-                let property = conn
-                    .get_property(
-                        false,
-                        window_id,
-                        AtomEnum::WM_CLASS,
-                        AtomEnum::STRING,
-                        0,
-                        1024,
-                    )?
-                    .reply()?;
+fn intersect_rectangles(a: Rectangle, b: Rectangle) -> Option<Rectangle> {
+    let (a_x, a_y, a_width, a_height) = a;
+    let (b_x, b_y, b_width, b_height) = b;
 
-                let mut iter = property.value.split(|x| *x == 0);
-                let wm_instance = iter.nth(1);
+    let left = a_x.max(b_x) as i32;
+    let top = a_y.max(b_y) as i32;
+    let right = (a_x as i32 + a_width as i32).min(b_x as i32 + b_width as i32);
+    let bottom =
+        (a_y as i32 + a_height as i32).min(b_y as i32 + b_height as i32);
 
-                wm_instance.map(|x| x.to_vec())
-            }
+    if left >= right || top >= bottom {
+        return None;
+    }
 
-            WindowInfoType::NetWmName => {
-                let property = conn
-                    .get_property(
-                        false,
-                        window_id,
-                        atoms._NET_WM_NAME,
-                        atoms.UTF8_STRING,
-                        0,
-                        1024,
-                    )?
-                    .reply()?;
+    Some((
+        left as i16,
+        top as i16,
+        (right - left) as u16,
+        (bottom - top) as u16,
+    ))
+}
 
-                let wm_name = property.value;
+// Copies pixels of the rectangle from one image to another. Both images have
+// 4 bytes per pixel and the given positions, and contain the rectangle
+fn copy_rectangle(
+    src: &[u8],
+    src_area: Rectangle,
+    dst: &mut [u8],
+    dst_area: Rectangle,
+    rectangle: Rectangle,
+) {
+    let (x, y, width, height) = rectangle;
+    let row_len = width as usize * 4;
+
+    let offset = |area: Rectangle, row: u16| {
+        let (area_x, area_y, area_width, _) = area;
+        let (column, line) = ((x - area_x) as usize, (y - area_y) as usize);
+
+        ((line + row as usize) * area_width as usize + column) * 4
+    };
 
-                Some(wm_name)
-            }
+    for row in 0..height {
+        let src_start = offset(src_area, row);
+        let dst_start = offset(dst_area, row);
 
-            WindowInfoType::NetWmVisibleName => {
-                let property = conn
-                    .get_property(
-                        false,
-                        window_id,
-                        atoms._NET_WM_VISIBLE_NAME,
-                        atoms.UTF8_STRING,
-                        0,
-                        1024,
-                    )?
-                    .reply()?;
+        dst[dst_start..dst_start + row_len]
+            .copy_from_slice(&src[src_start..src_start + row_len]);
+    }
+}
 
-                let wm_name = property.value;
+// Blends RGBA data onto BGRX background, that is returned by GetImage
+fn blend_onto_background(data: &mut [u8], background: &[u8]) {
+    data.chunks_exact_mut(4)
+        .zip(background.chunks_exact(4))
+        .for_each(|(pixel, bg)| {
+            let a = pixel[3] as f64 / 255.0;
+            let (bg_r, bg_g, bg_b) = (bg[2], bg[1], bg[0]);
+
+            pixel[0] = ((1.0 - a) * bg_r as f64 + a * pixel[0] as f64) as u8;
+            pixel[1] = ((1.0 - a) * bg_g as f64 + a * pixel[1] as f64) as u8;
+            pixel[2] = ((1.0 - a) * bg_b as f64 + a * pixel[2] as f64) as u8;
+            pixel[3] = 255;
+        });
+}
 
-                Some(wm_name)
-            }
+// Loads icon from the file and fits it into the square of the given size.
+// Svg icons are rasterized at exactly this size, so they stay sharp
+fn load_icon_image(path: &Path, size: u16) -> anyhow::Result<RgbaImage> {
+    if path.extension().is_some_and(|x| x == "svg") {
+        return render_svg(path, size as u32);
+    }
 
-            WindowInfoType::WmName => {
-                let property = conn
-                    .get_property(
-                        false,
-                        window_id,
-                        AtomEnum::WM_NAME,
-                        AtomEnum::STRING,
-                        0,
-                        1024,
-                    )?
-                    .reply()?;
+    let image = ImageReader::open(path)?.decode()?;
+    let image = image.resize(size as u32, size as u32, FilterType::CatmullRom);
 
-                let wm_name = property.value;
+    Ok(image.into_rgba8())
+}
 
-                Some(wm_name)
-            }
-        };
+fn render_svg(path: &Path, size: u32) -> anyhow::Result<RgbaImage> {
+    let data = fs::read(path)?;
+    let tree = usvg::Tree::from_data(&data, &usvg::Options::default())?;
+
+    let (svg_width, svg_height) = (tree.size.width(), tree.size.height());
+    let scale = (size as f32 / svg_width).min(size as f32 / svg_height);
+    let width = ((svg_width * scale).round() as u32).max(1);
+    let height = ((svg_height * scale).round() as u32).max(1);
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .context("Couldn't create pixmap for svg icon")?;
+
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    // Pixmap stores colors premultiplied by alpha, but we work with straight
+    // alpha everywhere
+    let buf = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+
+    RgbaImage::from_raw(width, height, buf)
+        .context("Couldn't convert svg icon to image")
+}
 
-        if let Some(bytes) = info_bytes {
-            if !bytes.is_empty() {
-                return Ok(WindowInfo {
-                    info: String::from_utf8_lossy(&bytes).to_string(),
-                    info_type: info_type.to_owned(),
-                });
-            }
-        }
-    }
+fn parse_color(color: &str) -> (u8, u8, u8) {
+    let bg_color = color[1..]
+        .chars()
+        .collect::<Vec<_>>()
+        .chunks_exact(2)
+        .map(|x| u8::from_str_radix(&format!("{}{}", x[0], x[1]), 16).unwrap())
+        .collect::<Vec<u8>>();
 
-    Ok(WindowInfo {
-        info: String::new(),
-        info_type: info_types.last().unwrap().to_owned(),
-    })
+    (bg_color[0], bg_color[1], bg_color[2])
 }
 
-pub fn is_window_fullscreen(window_id: u32) -> anyhow::Result<bool> {
-    let (conn, _) = x11rb::connect(None)?;
-    let atoms = AtomCollection::new(&conn)?.reply()?;
-
-    let property = conn
-        .get_property(
-            false,
-            window_id,
-            atoms._NET_WM_STATE,
-            AtomEnum::ATOM,
-            0,
-            1024,
-        )?
-        .reply()?;
+// Blends RGBA data onto the given color in place
+fn fill_background(data: &mut [u8], color: &str) {
+    let (bg_r, bg_g, bg_b) = parse_color(color);
 
-    let data = property.value;
+    data.chunks_exact_mut(4).for_each(|pixel| {
+        let a = pixel[3] as f64 / 255.0;
 
-    if data.is_empty() {
-        return Ok(false);
-    }
+        pixel[0] = ((1.0 - a) * bg_r as f64 + a * pixel[0] as f64) as u8;
+        pixel[1] = ((1.0 - a) * bg_g as f64 + a * pixel[1] as f64) as u8;
+        pixel[2] = ((1.0 - a) * bg_b as f64 + a * pixel[2] as f64) as u8;
+        pixel[3] = 255;
+    });
+}
 
-    // Read property value by chunks of 4 bytes, because there might be more,
-    // than one Atom specified in _NET_WM_STATE
-    Ok(data.chunks_exact(4).fold(false, |acc, chunk| {
-        let net_wm_state_atom = u32::from_le_bytes(chunk.try_into().unwrap());
+fn premultiply_alpha(data: &mut [u8]) {
+    data.chunks_exact_mut(4).for_each(|chunk| {
+        let alpha = chunk[3] as u16;
 
-        acc || (net_wm_state_atom == atoms._NET_WM_STATE_FULLSCREEN)
-    }))
+        for color in chunk[..3].iter_mut() {
+            *color = ((*color as u16 * alpha) / 255) as u8;
+        }
+    });
 }
 
-#[allow(unused)]
 fn save_transparent_image(
     image_data: &ImageData,
     icon_path: &str,
@@ -477,14 +1795,7 @@ fn save_filled_image(
     icon_path: &str,
     color: &str,
 ) -> anyhow::Result<()> {
-    let bg_color = color[1..]
-        .chars()
-        .collect::<Vec<_>>()
-        .chunks_exact(2)
-        .map(|x| u8::from_str_radix(&format!("{}{}", x[0], x[1]), 16).unwrap())
-        .collect::<Vec<u8>>();
-
-    let (bg_r, bg_g, bg_b) = (bg_color[0], bg_color[1], bg_color[2]);
+    let (bg_r, bg_g, bg_b) = parse_color(color);
 
     let mut new_img = vec![
         0u8;
@@ -522,15 +1833,12 @@ fn save_filled_image(
     Ok(())
 }
 
-pub fn generate_icon(
-    icon_name: &str,
-    cache_dir: &Path,
-    color: &str,
+// Returns the biggest of the icons, specified in _NET_WM_ICON
+fn get_net_wm_icon<Conn: Connection>(
+    conn: &Conn,
+    atoms: &AtomCollection,
     window_id: u32,
-) -> anyhow::Result<()> {
-    let (conn, _) = x11rb::connect(None)?;
-    let atoms = AtomCollection::new(&conn)?.reply()?;
-
+) -> anyhow::Result<Option<ImageData>> {
     let property = conn
         .get_property(
             false,
@@ -574,26 +1882,173 @@ pub fn generate_icon(
         }
     }
 
-    let icon_path =
-        format!("{}/{}.jpg", cache_dir.to_string_lossy(), icon_name);
-
     let mut max_size = 0;
     let mut max_icon = None;
 
-    for icon in icons.iter() {
+    for icon in icons {
         if icon.width > max_size {
             max_size = icon.width;
             max_icon = Some(icon);
         }
     }
 
-    match max_icon {
-        Some(icon) => save_filled_image(icon, &icon_path, color),
-        None => bail!("No icon was found for this window"),
+    Ok(max_icon)
+}
+
+// Reads icon from `icon_pixmap` and `icon_mask` of WM_HINTS, which is the way
+// classic X applications (like xterm) provide their icons
+fn get_wm_hints_icon<Conn: Connection>(
+    conn: &Conn,
+    window_id: u32,
+) -> anyhow::Result<Option<ImageData>> {
+    // Reply can't be parsed, if window doesn't have WM_HINTS at all
+    let Ok(hints) = WmHints::get(conn, window_id)?.reply() else {
+        return Ok(None);
+    };
+
+    let Some(icon_pixmap) = hints.icon_pixmap else {
+        return Ok(None);
+    };
+
+    let (width, height, mut buf) = read_pixmap(conn, icon_pixmap)?;
+
+    if let Some(icon_mask) = hints.icon_mask {
+        let (mask_width, mask_height, mask) = read_pixmap(conn, icon_mask)?;
+
+        if (mask_width, mask_height) == (width, height) {
+            apply_icon_mask(&mut buf, &mask);
+        }
+    }
+
+    Ok(Some(ImageData {
+        width: width as u32,
+        height: height as u32,
+        buf,
+    }))
+}
+
+// Sets alpha of the icon from its mask, converted by `pixmap_to_rgba`. Set
+// bits of the mask, which mark the shape of the icon, are converted to black
+// there, so black pixels become opaque
+fn apply_icon_mask(buf: &mut [u8], mask: &[u8]) {
+    buf.chunks_exact_mut(4)
+        .zip(mask.chunks_exact(4))
+        .for_each(|(pixel, mask_pixel)| pixel[3] = 255 - mask_pixel[0]);
+}
+
+// Reads the content of the pixmap and converts it to RGBA
+fn read_pixmap<Conn: Connection>(
+    conn: &Conn,
+    pixmap: Pixmap,
+) -> anyhow::Result<(u16, u16, Vec<u8>)> {
+    let geometry = conn.get_geometry(pixmap)?.reply()?;
+    let (width, height, depth) =
+        (geometry.width, geometry.height, geometry.depth);
+
+    let image = conn
+        .get_image(ImageFormat::Z_PIXMAP, pixmap, 0, 0, width, height, !0)?
+        .reply()?;
+
+    let setup = conn.setup();
+    let Some(format) = setup.pixmap_formats.iter().find(|x| x.depth == depth)
+    else {
+        bail!("Unknown format of pixmap with depth {depth}");
+    };
+
+    let Some(buf) = pixmap_to_rgba(
+        &image.data,
+        width as usize,
+        height as usize,
+        format.bits_per_pixel as usize,
+        format.scanline_pad as usize,
+        setup.bitmap_format_bit_order == ImageOrder::LSB_FIRST,
+    ) else {
+        bail!(
+            "Unsupported pixmap with {} bits per pixel",
+            format.bits_per_pixel
+        );
+    };
+
+    Ok((width, height, buf))
+}
+
+// Converts data of Z_PIXMAP image to RGBA. Bitmaps (1 bit per pixel) are
+// converted to black foreground on white background, as X clients expect
+// them to be drawn
+fn pixmap_to_rgba(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    bits_per_pixel: usize,
+    scanline_pad: usize,
+    lsb_first: bool,
+) -> Option<Vec<u8>> {
+    let stride = image_stride(width, bits_per_pixel, scanline_pad);
+
+    if data.len() < stride * height {
+        return None;
+    }
+
+    let mut buf = Vec::with_capacity(width * height * 4);
+
+    for y in 0..height {
+        let row = &data[y * stride..(y + 1) * stride];
+
+        for x in 0..width {
+            match bits_per_pixel {
+                1 => {
+                    let bit = if lsb_first { x % 8 } else { 7 - x % 8 };
+                    let is_set = row[x / 8] & (1 << bit) != 0;
+                    let color = if is_set { 0 } else { 255 };
+
+                    buf.extend([color, color, color, 255]);
+                }
+
+                32 => {
+                    let pixel = &row[x * 4..x * 4 + 4];
+                    buf.extend([pixel[2], pixel[1], pixel[0], 255]);
+                }
+
+                _ => return None,
+            }
+        }
+    }
+
+    Some(buf)
+}
+
+// Number of bytes in a row of an image, which is padded to `scanline_pad` bits
+fn image_stride(
+    width: usize,
+    bits_per_pixel: usize,
+    scanline_pad: usize,
+) -> usize {
+    (width * bits_per_pixel).div_ceil(scanline_pad) * scanline_pad / 8
+}
+
+fn save_icon(
+    icon: &ImageData,
+    icon_name: &str,
+    cache_dir: &Path,
+    color: &str,
+    style: IconStyle,
+) -> anyhow::Result<()> {
+    let icon_path = format!(
+        "{}/{}.{}",
+        cache_dir.to_string_lossy(),
+        icon_name,
+        style.extension()
+    );
+
+    match style {
+        IconStyle::Filled => save_filled_image(icon, &icon_path, color),
+        IconStyle::Argb | IconStyle::Shaped { .. } | IconStyle::Sampled => {
+            save_transparent_image(icon, &icon_path)
+        }
     }
 }
 
-#[allow(unused)]
+#[cfg(feature = "x11")]
 fn composite_manager_running(
     conn: &impl Connection,
     screen_num: usize,
@@ -607,48 +2062,74 @@ fn composite_manager_running(
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "bspwm")]
     use bspc_rs::query;
+    #[cfg(feature = "bspwm")]
     use bspc_rs::selectors::NodeSelector;
     use std::env;
 
     #[test]
+    fn parse_xft_dpi_works() {
+        let resources = "Xft.antialias:\t1\nXft.dpi:\t192\nXcursor.size:\t48\n";
+
+        assert_eq!(parse_xft_dpi(resources), Some(192.0));
+        assert_eq!(parse_xft_dpi("Xft.antialias:\t1\n"), None);
+        assert_eq!(parse_xft_dpi("Xft.dpi:\tlarge\n"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "bspwm")]
     fn test_get_wm_class() {
         let id =
             query::query_nodes(None, None, None, Some(NodeSelector("focused")))
                 .unwrap()[0];
-        let wm_class = get_wm_class(id).unwrap();
+        let x_context = XContext::new(None).unwrap();
+        let wm_class = x_context.get_wm_class(id).unwrap();
 
         println!("{wm_class}");
     }
 
     #[test]
     fn test_get_current_wm() {
-        let wm = get_current_wm().unwrap();
+        let x_context = XContext::new(None).unwrap();
+        let wm = x_context.get_current_wm().unwrap();
 
         println!("wm: {wm}");
     }
 
     #[test]
+    #[cfg(feature = "bspwm")]
     fn test_is_window_fullscreen() {
         let id =
             query::query_nodes(None, None, None, Some(NodeSelector("focused")))
                 .unwrap()[0];
-        let flag = is_window_fullscreen(id).unwrap();
+        let x_context = XContext::new(None).unwrap();
+        let flag = x_context.is_window_fullscreen(id).unwrap();
 
         println!("flag: {flag}");
     }
 
     #[test]
     #[ignore]
+    #[cfg(feature = "bspwm")]
     fn test_generate_icon() {
         let id =
             query::query_nodes(None, None, None, Some(NodeSelector("focused")))
                 .unwrap()[0];
-
-        generate_icon("foo.jpg", Path::new("/home/andrey"), "#252737", id)
+        let x_context = XContext::new(None).unwrap();
+
+        x_context
+            .generate_icon(
+                "foo.jpg",
+                Path::new("/home/andrey"),
+                "#252737",
+                id,
+                IconStyle::Filled,
+            )
             .unwrap();
     }
 
+    #[cfg(feature = "x11")]
     fn get_icon_path() -> String {
         env::current_dir().unwrap().to_str().unwrap().to_owned()
             + "/tests_files/alacritty.png"
@@ -656,19 +2137,237 @@ mod tests {
 
     #[test]
     #[ignore]
+    #[cfg(feature = "x11")]
     fn display_icon_test() {
-        let (conn, _) = x11rb::connect(None).unwrap();
-        let monitor_name = get_primary_monitor_name()
+        let x_context = XContext::new(None).unwrap();
+        let monitor_name = x_context
+            .get_primary_monitor_name()
             .expect("Couldn't get name of primary monitor");
+        let mut icon_window =
+            x_context.create_icon_window(IconStyle::Filled).unwrap();
+
+        x_context
+            .display_icon(
+                &mut icon_window,
+                Path::new(&get_icon_path()),
+                270,
+                6,
+                24,
+                &monitor_name,
+                &[],
+                false,
+                IconStyle::Filled,
+                "#252737",
+            )
+            .unwrap();
+    }
 
-        display_icon(
-            &conn,
-            Path::new(&get_icon_path()),
-            270,
-            6,
-            24,
-            &monitor_name,
+    // Creates window, that is found by the default bar matcher, at the top
+    // of the monitor
+    #[cfg(feature = "x11")]
+    fn create_fake_bar(x_context: &XContext, monitor_name: &str) -> Window {
+        let conn = &x_context.conn;
+        let (x, y, width, _) =
+            x_context.get_monitor_geometry(monitor_name).unwrap();
+        let window = conn.generate_id().unwrap();
+
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            x_context.screen().root,
+            x,
+            y,
+            width,
+            36,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::default().override_redirect(1),
+        )
+        .unwrap();
+        conn.change_property8(
+            PropMode::REPLACE,
+            window,
+            AtomEnum::WM_CLASS,
+            AtomEnum::STRING,
+            b"polybar\0Polybar\0",
         )
         .unwrap();
+        conn.map_window(window).unwrap();
+        conn.sync().unwrap();
+
+        window
+    }
+
+    #[test]
+    #[ignore]
+    #[cfg(feature = "x11")]
+    fn reparent_into_recreated_bar_test() {
+        let x_context = XContext::new(None).unwrap();
+        let conn = &x_context.conn;
+        let monitor_name = x_context
+            .get_primary_monitor_name()
+            .expect("Couldn't get name of primary monitor");
+        let matchers = [BarMatcher {
+            instance: Some("polybar".to_string()),
+            class: Some("Polybar".to_string()),
+            ..Default::default()
+        }];
+
+        let display = |icon_window: &mut IconWindow| {
+            let bars = x_context.get_bars(&matchers, &monitor_name).unwrap();
+
+            x_context
+                .display_icon(
+                    icon_window,
+                    Path::new(&get_icon_path()),
+                    6,
+                    6,
+                    24,
+                    &monitor_name,
+                    &bars,
+                    true,
+                    IconStyle::Filled,
+                    "#252737",
+                )
+                .unwrap();
+        };
+
+        let old_bar = create_fake_bar(&x_context, &monitor_name);
+        let mut icon_window =
+            x_context.create_icon_window(IconStyle::Filled).unwrap();
+        display(&mut icon_window);
+        assert_eq!(icon_window.parent, Some(old_bar));
+
+        // Icon window is destroyed together with the bar, so a new one is
+        // created for the next bar
+        conn.destroy_window(old_bar).unwrap();
+        conn.sync().unwrap();
+        x_context.free_icon_resources(&icon_window).unwrap();
+        assert!(x_context
+            .get_bars(&matchers, &monitor_name)
+            .unwrap()
+            .is_empty());
+
+        let new_bar = create_fake_bar(&x_context, &monitor_name);
+        assert!(x_context
+            .get_bar(new_bar, &matchers, &monitor_name)
+            .unwrap()
+            .is_some());
+
+        let mut icon_window =
+            x_context.create_icon_window(IconStyle::Filled).unwrap();
+        display(&mut icon_window);
+        assert_eq!(icon_window.parent, Some(new_bar));
+
+        let children = conn.query_tree(new_bar).unwrap().reply().unwrap();
+        assert!(children.children.contains(&icon_window.window));
+
+        x_context.destroy_icon(&icon_window).unwrap();
+        conn.destroy_window(new_bar).unwrap();
+        conn.sync().unwrap();
+    }
+
+    #[test]
+    fn render_svg_works() {
+        let icon_path = env::current_dir()
+            .unwrap()
+            .join("examples/custom-icons/discord.svg");
+
+        for size in [24, 48] {
+            let image = render_svg(&icon_path, size).unwrap();
+            assert_eq!(image.dimensions(), (size, size));
+        }
+    }
+
+    #[test]
+    fn fill_background_works() {
+        let mut data = vec![255, 255, 255, 255, 255, 255, 255, 0];
+        fill_background(&mut data, "#252737");
+
+        assert_eq!(data, vec![255, 255, 255, 255, 0x25, 0x27, 0x37, 255]);
+    }
+
+    #[test]
+    fn premultiply_alpha_works() {
+        let mut data =
+            vec![255, 128, 0, 128, 10, 20, 30, 255, 200, 200, 200, 0];
+        premultiply_alpha(&mut data);
+
+        assert_eq!(data, vec![128, 64, 0, 128, 10, 20, 30, 255, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn blend_onto_background_works() {
+        let mut data = vec![255, 0, 0, 255, 0, 0, 255, 0, 0, 255, 0, 51];
+        let background = vec![0, 0, 255, 0, 10, 20, 30, 0, 0, 0, 0, 0];
+        blend_onto_background(&mut data, &background);
+
+        assert_eq!(data, vec![255, 0, 0, 255, 30, 20, 10, 255, 0, 51, 0, 255]);
+    }
+
+    #[test]
+    fn copy_rectangle_works() {
+        // Icon of 2x2 moves one pixel right, so the left column of the new
+        // area is still covered by it
+        let previous_area = (0, 0, 2, 2);
+        let area = (1, 0, 2, 2);
+        let covered = intersect_rectangles(previous_area, area).unwrap();
+        assert_eq!(covered, (1, 0, 1, 2));
+        assert_eq!(intersect_rectangles(previous_area, (2, 0, 2, 2)), None);
+
+        let previous: Vec<u8> =
+            [1, 2, 3, 4].iter().flat_map(|&x| [x; 4]).collect();
+        let mut data = vec![0; 16];
+        copy_rectangle(&previous, previous_area, &mut data, area, covered);
+
+        assert_eq!(data, [[2; 4], [0; 4], [4; 4], [0; 4]].concat());
+    }
+
+    #[test]
+    fn pixmap_to_rgba_works() {
+        // 2x2 bitmap with rows padded to 8 bits
+        let bitmap = vec![0b01, 0b10];
+        let buf = pixmap_to_rgba(&bitmap, 2, 2, 1, 8, true).unwrap();
+
+        assert_eq!(
+            buf,
+            vec![
+                0, 0, 0, 255, 255, 255, 255, 255, // first row
+                255, 255, 255, 255, 0, 0, 0, 255, // second row
+            ]
+        );
+
+        let pixmap = vec![10, 20, 30, 0, 40, 50, 60, 0];
+        let buf = pixmap_to_rgba(&pixmap, 2, 1, 32, 32, true).unwrap();
+        assert_eq!(buf, vec![30, 20, 10, 255, 60, 50, 40, 255]);
+
+        assert!(pixmap_to_rgba(&pixmap, 2, 1, 16, 32, true).is_none());
+    }
+
+    #[test]
+    fn apply_icon_mask_works() {
+        let pixmap = vec![10, 20, 30, 0, 40, 50, 60, 0, 70, 80, 90, 0];
+        let mut buf = pixmap_to_rgba(&pixmap, 3, 1, 32, 32, true).unwrap();
+
+        // Only the first and the last pixels belong to the shape
+        let bitmap = vec![0b101];
+        let mask = pixmap_to_rgba(&bitmap, 3, 1, 1, 8, true).unwrap();
+        apply_icon_mask(&mut buf, &mask);
+
+        assert_eq!(buf, vec![30, 20, 10, 255, 60, 50, 40, 0, 90, 80, 70, 255]);
+    }
+
+    #[test]
+    fn build_shape_mask_works() {
+        // 3x2 image with alpha channel only being meaningful
+        let alphas = [255, 0, 200, 10, 128, 127];
+        let data: Vec<u8> = alphas.iter().flat_map(|&a| [0, 0, 0, a]).collect();
+
+        let lsb_mask = build_shape_mask(&data, 3, 128, 32, true);
+        assert_eq!(lsb_mask, vec![0b101, 0, 0, 0, 0b010, 0, 0, 0]);
+
+        let msb_mask = build_shape_mask(&data, 3, 128, 8, false);
+        assert_eq!(msb_mask, vec![0b1010_0000, 0b0100_0000]);
     }
 }